@@ -1,27 +1,579 @@
 //! Account Activity API
-use self::crypto::mac::Mac;
-use self::ipnetwork::Ipv4Network;
-use std::net::Ipv4Addr;
+use crate::models::{DirectMessageEvent, MessageTarget, Tweet, User};
+use async_trait::async_trait;
+use hmac::{Hmac, Mac, NewMac};
+use http::{header, HeaderValue, Method, Request, Response, StatusCode};
+use ipnetwork::IpNetwork;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{collections::HashMap, fmt, net::IpAddr};
+
+/// Networks webhook requests come from
+pub const TWITTER_NETWORKS: [&str; 2] = ["199.59.148.0/22", "199.16.156.0/22"];
+
+/// Error of webhook request verification
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerifyError {
+    MissingSignature,
+    /// Not sha256=<base64>
+    MalformedSignature(String),
+    SignatureMismatch,
+    InvalidIp(String),
+    InvalidNetwork(String),
+    IpNotAllowed(IpAddr),
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::MissingSignature => write!(f, "x-twitter-webhooks-signature is missing"),
+            VerifyError::MalformedSignature(signature) => {
+                write!(f, "signature is not sha256=<base64>: {}", signature)
+            }
+            VerifyError::SignatureMismatch => write!(f, "signature does not match body"),
+            VerifyError::InvalidIp(ip) => write!(f, "invalid ip address: {}", ip),
+            VerifyError::InvalidNetwork(network) => write!(f, "invalid network: {}", network),
+            VerifyError::IpNotAllowed(ip) => write!(f, "ip address is not allowed: {}", ip),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+fn make_hmac(key: &str, input: &[u8]) -> Hmac<Sha256> {
+    let mut hmac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC can take key of any size");
+    hmac.update(input);
+    hmac
+}
 
 pub fn calc_hmac(key: &str, input: &str) -> String {
-    let mut hmac = crypto::hmac::Hmac::new(crypto::sha2::Sha256::new(), key.as_bytes());
-    hmac.input(input.as_bytes());
-    base64::encode(hmac.result().code())
+    base64::encode(make_hmac(key, input.as_bytes()).finalize().into_bytes())
+}
+
+/// Verify x-twitter-webhooks-signature against raw body in constant time
+pub fn verify_signature(
+    signature: &str,
+    consumer_secret: &str,
+    body: &[u8],
+) -> Result<(), VerifyError> {
+    let encoded = signature
+        .strip_prefix("sha256=")
+        .ok_or_else(|| VerifyError::MalformedSignature(String::from(signature)))?;
+    let decoded = base64::decode(encoded)
+        .map_err(|_| VerifyError::MalformedSignature(String::from(signature)))?;
+    make_hmac(consumer_secret, body)
+        .verify(&decoded)
+        .map_err(|_| VerifyError::SignatureMismatch)
 }
 
 pub fn check_signature(signature: &str, consumer_secret: &str, body: &str) -> bool {
-    let calced_body = calc_hmac(consumer_secret, body);
-    signature == format!("sha256={}", calced_body)
+    verify_signature(signature, consumer_secret, body.as_bytes()).is_ok()
+}
+
+/// Allowed networks of webhook requests, IPv4 and IPv6
+#[derive(Debug, Clone, PartialEq)]
+pub struct IpAllowlist {
+    networks: Vec<IpNetwork>,
+}
+
+impl Default for IpAllowlist {
+    /// TWITTER_NETWORKS
+    fn default() -> IpAllowlist {
+        IpAllowlist::new(&TWITTER_NETWORKS).expect("TWITTER_NETWORKS are valid")
+    }
+}
+
+impl IpAllowlist {
+    /// networks in CIDR notation such as 199.59.148.0/22 or 2001:db8::/32
+    pub fn new(networks: &[&str]) -> Result<IpAllowlist, VerifyError> {
+        networks
+            .iter()
+            .try_fold(IpAllowlist { networks: vec![] }, |it, network| {
+                it.with_network(network)
+            })
+    }
+
+    pub fn with_network(mut self, network: &str) -> Result<IpAllowlist, VerifyError> {
+        let network = network
+            .parse()
+            .map_err(|_| VerifyError::InvalidNetwork(String::from(network)))?;
+        self.networks.push(network);
+        Ok(self)
+    }
+
+    pub fn networks(&self) -> &[IpNetwork] {
+        &self.networks
+    }
+
+    /// IPv4-mapped IPv6 address such as ::ffff:199.59.148.1 is checked as IPv4
+    pub fn verify(&self, ip: &str) -> Result<(), VerifyError> {
+        let target: IpAddr = ip
+            .trim()
+            .parse()
+            .map_err(|_| VerifyError::InvalidIp(String::from(ip)))?;
+        let target = match target {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(target, IpAddr::V4),
+            IpAddr::V4(_) => target,
+        };
+        if self.networks.iter().any(|it| it.contains(target)) {
+            Ok(())
+        } else {
+            Err(VerifyError::IpNotAllowed(target))
+        }
+    }
 }
 
+/// Check ip is in TWITTER_NETWORKS, false for malformed address
 pub fn check_ip(ip: &str) -> bool {
-    let net1: Ipv4Network = "199.59.148.0/22".parse().unwrap();
-    let net2: Ipv4Network = "199.16.156.0/22".parse().unwrap();
-    let target: Ipv4Addr = ip.parse().unwrap();
-    net1.contains(target) || net2.contains(target)
+    IpAllowlist::default().verify(ip).is_ok()
 }
 
 pub fn make_crc_token_response(consumer_secret: &str, crc_token: &str) -> String {
     let calced = calc_hmac(consumer_secret, crc_token);
     format!("{{\"response_token\":\"sha256={}\"}}", calced)
 }
+
+/// Webhook body of Account Activity API
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccountActivity {
+    pub for_user_id: String,
+    pub user_has_blocked: Option<bool>,
+    pub events: Vec<AccountActivityEvent>,
+    /// Users referenced by direct message events, keyed by id
+    pub users: HashMap<String, User>,
+    /// Apps referenced by direct message events, keyed by id
+    pub apps: HashMap<String, serde_json::Value>,
+}
+
+/// One event of webhook body
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccountActivityEvent {
+    TweetCreate(Tweet),
+    Favorite(FavoriteEvent),
+    /// type is follow or unfollow
+    Follow(UserEvent),
+    /// type is block or unblock
+    Block(UserEvent),
+    /// type is mute or unmute
+    Mute(UserEvent),
+    DirectMessage(DirectMessageEvent),
+    DirectMessageIndicateTyping(DirectMessageIndicateTypingEvent),
+    DirectMessageMarkRead(DirectMessageMarkReadEvent),
+    TweetDelete(TweetDeleteEvent),
+    /// Events not covered above, with the key such as user_event
+    Unknown(String, serde_json::Value),
+}
+
+/// favorite_events
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FavoriteEvent {
+    pub id: String,
+    pub created_at: String,
+    pub timestamp_ms: u64,
+    pub favorited_status: Tweet,
+    pub user: User,
+}
+
+/// follow_events, block_events and mute_events
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserEvent {
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub created_timestamp: String,
+    pub target: User,
+    pub source: User,
+}
+
+/// direct_message_indicate_typing_events
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DirectMessageIndicateTypingEvent {
+    pub created_timestamp: String,
+    pub sender_id: String,
+    pub target: MessageTarget,
+}
+
+/// direct_message_mark_read_events
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DirectMessageMarkReadEvent {
+    pub created_timestamp: String,
+    pub sender_id: String,
+    pub target: MessageTarget,
+    pub last_read_event_id: String,
+}
+
+/// tweet_delete_events
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TweetDeleteEvent {
+    pub status: DeletedStatus,
+    pub timestamp_ms: String,
+}
+
+/// status of tweet_delete_events
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeletedStatus {
+    pub id: String,
+    pub user_id: String,
+}
+
+#[derive(Deserialize)]
+struct RawAccountActivity {
+    #[serde(default)]
+    for_user_id: String,
+    #[serde(default)]
+    user_has_blocked: Option<bool>,
+    #[serde(default)]
+    users: HashMap<String, User>,
+    #[serde(default)]
+    apps: HashMap<String, serde_json::Value>,
+    #[serde(flatten)]
+    rest: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Parse webhook body
+pub fn parse_account_activity(body: &str) -> Result<AccountActivity, serde_json::Error> {
+    let raw: RawAccountActivity = serde_json::from_str(body)?;
+    let mut events = vec![];
+    for (key, value) in raw.rest {
+        let values = match value {
+            serde_json::Value::Array(values) => values,
+            value => {
+                events.push(AccountActivityEvent::Unknown(key, value));
+                continue;
+            }
+        };
+        for value in values {
+            events.push(parse_event(&key, value)?);
+        }
+    }
+    Ok(AccountActivity {
+        for_user_id: raw.for_user_id,
+        user_has_blocked: raw.user_has_blocked,
+        events,
+        users: raw.users,
+        apps: raw.apps,
+    })
+}
+
+fn parse_event(
+    key: &str,
+    value: serde_json::Value,
+) -> Result<AccountActivityEvent, serde_json::Error> {
+    use serde_json::from_value;
+    Ok(match key {
+        "tweet_create_events" => AccountActivityEvent::TweetCreate(from_value(value)?),
+        "favorite_events" => AccountActivityEvent::Favorite(from_value(value)?),
+        "follow_events" => AccountActivityEvent::Follow(from_value(value)?),
+        "block_events" => AccountActivityEvent::Block(from_value(value)?),
+        "mute_events" => AccountActivityEvent::Mute(from_value(value)?),
+        "direct_message_events" => AccountActivityEvent::DirectMessage(from_value(value)?),
+        "direct_message_indicate_typing_events" => {
+            AccountActivityEvent::DirectMessageIndicateTyping(from_value(value)?)
+        }
+        "direct_message_mark_read_events" => {
+            AccountActivityEvent::DirectMessageMarkRead(from_value(value)?)
+        }
+        "tweet_delete_events" => AccountActivityEvent::TweetDelete(from_value(value)?),
+        _ => AccountActivityEvent::Unknown(String::from(key), value),
+    })
+}
+
+/// Receiver of webhook events dispatched by WebhookHandler
+#[async_trait]
+pub trait AccountActivityHandler {
+    async fn on_event(&self, activity: &AccountActivity, event: &AccountActivityEvent);
+}
+
+/// Webhook endpoint independent of web frameworks.
+/// Answer CRC on GET, verify and dispatch events on POST.
+pub struct WebhookHandler<H> {
+    consumer_secret: String,
+    ip_allowlist: Option<IpAllowlist>,
+    handler: H,
+}
+
+impl<H: AccountActivityHandler + Send + Sync> WebhookHandler<H> {
+    pub fn new(consumer_secret: &str, handler: H) -> WebhookHandler<H> {
+        WebhookHandler {
+            consumer_secret: String::from(consumer_secret),
+            ip_allowlist: None,
+            handler,
+        }
+    }
+
+    /// Reject requests not from TWITTER_NETWORKS
+    pub fn check_ip(mut self, check_ip: bool) -> WebhookHandler<H> {
+        self.ip_allowlist = if check_ip {
+            Some(IpAllowlist::default())
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Reject requests not from the networks
+    pub fn ip_allowlist(mut self, ip_allowlist: IpAllowlist) -> WebhookHandler<H> {
+        self.ip_allowlist = Some(ip_allowlist);
+        self
+    }
+
+    pub fn handler(&self) -> &H {
+        &self.handler
+    }
+
+    /// remote_ip is the peer address or X-Real-IP behind a proxy
+    pub async fn handle<B: AsRef<[u8]>>(
+        &self,
+        request: &Request<B>,
+        remote_ip: Option<&str>,
+    ) -> Response<String> {
+        if let Some(ref ip_allowlist) = self.ip_allowlist {
+            let result = match remote_ip {
+                Some(ip) => ip_allowlist.verify(ip),
+                None => Err(VerifyError::InvalidIp(String::new())),
+            };
+            if let Err(err) = result {
+                return make_response(StatusCode::FORBIDDEN, &err.to_string());
+            }
+        }
+        match *request.method() {
+            Method::GET => self.handle_crc(request),
+            Method::POST => self.handle_events(request).await,
+            _ => make_response(StatusCode::METHOD_NOT_ALLOWED, ""),
+        }
+    }
+
+    fn handle_crc<B>(&self, request: &Request<B>) -> Response<String> {
+        let query = request.uri().query().unwrap_or_default();
+        let crc_token = url::form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == "crc_token")
+            .map(|(_, value)| value.into_owned());
+        match crc_token {
+            Some(crc_token) => {
+                let mut response = make_response(
+                    StatusCode::OK,
+                    &make_crc_token_response(&self.consumer_secret, &crc_token),
+                );
+                response.headers_mut().insert(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static("application/json"),
+                );
+                response
+            }
+            None => make_response(StatusCode::BAD_REQUEST, ""),
+        }
+    }
+
+    async fn handle_events<B: AsRef<[u8]>>(&self, request: &Request<B>) -> Response<String> {
+        let body = request.body().as_ref();
+        let result = match request
+            .headers()
+            .get("x-twitter-webhooks-signature")
+            .and_then(|it| it.to_str().ok())
+        {
+            Some(signature) => verify_signature(signature, &self.consumer_secret, body),
+            None => Err(VerifyError::MissingSignature),
+        };
+        if let Err(err) = result {
+            return make_response(StatusCode::UNAUTHORIZED, &err.to_string());
+        }
+        let body = match std::str::from_utf8(body) {
+            Ok(body) => body,
+            Err(_) => return make_response(StatusCode::BAD_REQUEST, ""),
+        };
+        let activity = match parse_account_activity(body) {
+            Ok(activity) => activity,
+            Err(_) => return make_response(StatusCode::BAD_REQUEST, ""),
+        };
+        for event in activity.events.iter() {
+            self.handler.on_event(&activity, event).await;
+        }
+        make_response(StatusCode::OK, "")
+    }
+}
+
+fn make_response(status: StatusCode, body: &str) -> Response<String> {
+    let mut response = Response::new(String::from(body));
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    const SECRET: &str = "consumer_secret";
+
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<(String, AccountActivityEvent)>>,
+    }
+
+    #[async_trait]
+    impl AccountActivityHandler for Recorder {
+        async fn on_event(&self, activity: &AccountActivity, event: &AccountActivityEvent) {
+            self.events
+                .lock()
+                .unwrap()
+                .push((activity.for_user_id.clone(), event.clone()));
+        }
+    }
+
+    fn handler() -> WebhookHandler<Recorder> {
+        WebhookHandler::new(SECRET, Recorder::default())
+    }
+
+    fn post(body: &str, signature: Option<&str>) -> Request<String> {
+        let mut builder = Request::builder().method(Method::POST).uri("/webhook");
+        if let Some(signature) = signature {
+            builder = builder.header("x-twitter-webhooks-signature", signature);
+        }
+        builder.body(String::from(body)).unwrap()
+    }
+
+    fn sign(body: &str) -> String {
+        format!("sha256={}", calc_hmac(SECRET, body))
+    }
+
+    #[test]
+    fn allows_twitter_networks() {
+        assert!(check_ip("199.59.148.1"));
+        assert!(check_ip(" 199.16.159.254 "));
+        assert!(!check_ip("199.59.152.1"));
+        assert!(!check_ip("2001:db8::1"));
+    }
+
+    #[test]
+    fn allows_ipv4_mapped_ipv6() {
+        assert!(check_ip("::ffff:199.59.148.1"));
+        assert!(check_ip("::FFFF:c73b:9401"));
+        assert!(!check_ip("::ffff:192.0.2.1"));
+        // IPv4-compatible is not mapped
+        assert!(!check_ip("::199.59.148.1"));
+    }
+
+    #[test]
+    fn allows_ipv6_networks() {
+        let allowlist = IpAllowlist::new(&["2001:db8::/32", "10.0.0.0/8"]).unwrap();
+        assert_eq!(allowlist.verify("2001:db8:1::1"), Ok(()));
+        assert_eq!(allowlist.verify("::ffff:10.1.2.3"), Ok(()));
+        assert_eq!(
+            allowlist.verify("2001:db9::1"),
+            Err(VerifyError::IpNotAllowed("2001:db9::1".parse().unwrap()))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_ip() {
+        let allowlist = IpAllowlist::default();
+        for ip in ["", "199.59.148", "199.59.148.1:443", "[::1]", "example.com"].iter() {
+            assert_eq!(
+                allowlist.verify(ip),
+                Err(VerifyError::InvalidIp(String::from(*ip)))
+            );
+        }
+        assert_eq!(
+            IpAllowlist::new(&["199.59.148.0/33"]),
+            Err(VerifyError::InvalidNetwork(String::from("199.59.148.0/33")))
+        );
+    }
+
+    #[tokio::test]
+    async fn answers_crc_on_get() {
+        let request = Request::get("/webhook?crc_token=abc%2Bd")
+            .body(String::new())
+            .unwrap();
+        let response = handler().handle(&request, None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            HeaderValue::from_static("application/json")
+        );
+        assert_eq!(response.body(), &make_crc_token_response(SECRET, "abc+d"));
+    }
+
+    #[tokio::test]
+    async fn rejects_get_without_crc_token() {
+        let request = Request::get("/webhook").body(String::new()).unwrap();
+        let response = handler().handle(&request, None).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_signature() {
+        let handler = handler();
+        let response = handler.handle(&post("{}", None), None).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(handler.handler().events.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_bad_signature() {
+        let body = r#"{"for_user_id":"1","follow_events":[{"type":"follow"}]}"#;
+        let handler = handler();
+        for signature in ["sha256=AAAA", "abc", &sign("{}")].iter() {
+            let response = handler.handle(&post(body, Some(signature)), None).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{}", signature);
+        }
+        assert!(handler.handler().events.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_ip_not_allowed() {
+        let handler = handler().check_ip(true);
+        let request = Request::get("/webhook?crc_token=a")
+            .body(String::new())
+            .unwrap();
+        let response = handler.handle(&request, Some("192.0.2.1")).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = handler.handle(&request, None).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = handler.handle(&request, Some("199.59.148.1")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn rejects_other_methods() {
+        let request = Request::put("/webhook").body(String::new()).unwrap();
+        let response = handler().handle(&request, None).await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_body() {
+        let body = "not json";
+        let response = handler().handle(&post(body, Some(&sign(body))), None).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn dispatches_events() {
+        let body = r#"{
+            "for_user_id": "1",
+            "follow_events": [{"type": "follow", "source": {"id_str": "2"}}],
+            "tweet_delete_events": [{"status": {"id": "3", "user_id": "2"}, "timestamp_ms": "4"}]
+        }"#;
+        let handler = handler();
+        let response = handler.handle(&post(body, Some(&sign(body))), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let events = handler.handler().events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|(for_user_id, _)| for_user_id == "1"));
+        assert!(events.iter().any(|(_, event)| matches!(
+            event,
+            AccountActivityEvent::Follow(it) if it.event_type == "follow" && it.source.id_str == "2"
+        )));
+        assert!(events.iter().any(|(_, event)| matches!(
+            event,
+            AccountActivityEvent::TweetDelete(it) if it.status.id == "3"
+        )));
+    }
+}
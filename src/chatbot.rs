@@ -0,0 +1,575 @@
+//! Direct message bot on Account Activity webhook events
+//!
+//! Routes are plain closures over the incoming message and per-conversation state,
+//! so Bot::route can be tested without network and Bot::handle_event with any Twapi.
+use crate::{
+    account_activity::{AccountActivity, AccountActivityEvent, AccountActivityHandler},
+    direct_message::MessageBuilder,
+    models::{DirectMessageEvent, User},
+    Twapi, TwapiError,
+};
+use async_trait::async_trait;
+use regex::Regex;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Mutex,
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// Default number of event ids remembered to ignore redelivered webhook events
+pub const DEFAULT_REMEMBERED_EVENTS: usize = 1000;
+
+/// Direct message received by Bot
+#[derive(Debug, Clone, PartialEq)]
+pub struct Incoming {
+    pub event_id: String,
+    /// Conversation key of per-conversation state
+    pub sender_id: String,
+    pub text: String,
+    /// metadata of the selected quick reply option
+    pub quick_reply_metadata: Option<String>,
+    /// From users of the webhook body
+    pub sender: Option<User>,
+    /// Capture groups of the matched regex route, empty for unmatched groups
+    pub captures: Vec<String>,
+}
+
+impl Incoming {
+    pub fn from_event(event: &DirectMessageEvent) -> Option<Incoming> {
+        let message_create = event.message_create.as_ref()?;
+        Some(Incoming {
+            event_id: event.id.clone(),
+            sender_id: message_create.sender_id.clone()?,
+            text: message_create.message_data.text.clone(),
+            quick_reply_metadata: message_create
+                .message_data
+                .quick_reply_response
+                .as_ref()
+                .and_then(|it| it.metadata.clone()),
+            sender: None,
+            captures: vec![],
+        })
+    }
+
+    /// MessageBuilder to the sender
+    pub fn reply(&self, text: &str) -> MessageBuilder {
+        MessageBuilder::new(&self.sender_id, text)
+    }
+}
+
+type Handler<S> = Box<dyn Fn(&Incoming, &mut S) -> Option<MessageBuilder> + Send + Sync>;
+
+enum Matcher {
+    QuickReply(String),
+    Regex(Regex),
+}
+
+struct Conversation<S> {
+    state: S,
+    last_seen: Instant,
+}
+
+// Bounded set of handled event ids, the oldest is forgotten first
+struct SeenEvents {
+    capacity: usize,
+    ids: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+impl SeenEvents {
+    // false if already seen
+    fn insert(&mut self, id: &str) -> bool {
+        if self.capacity == 0 || id.is_empty() {
+            return true;
+        }
+        if !self.ids.insert(String::from(id)) {
+            return false;
+        }
+        self.order.push_back(String::from(id));
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+/// Router of direct messages with per-conversation state
+pub struct Bot<S> {
+    user_id: String,
+    routes: Vec<(Matcher, Handler<S>)>,
+    fallback: Option<Handler<S>>,
+    states: Mutex<HashMap<String, Conversation<S>>>,
+    state_ttl: Option<Duration>,
+    seen_events: Mutex<SeenEvents>,
+}
+
+impl<S: Default + Send> Bot<S> {
+    /// user_id of the bot account, its own messages are ignored
+    pub fn new(user_id: &str) -> Bot<S> {
+        Bot {
+            user_id: String::from(user_id),
+            routes: vec![],
+            fallback: None,
+            states: Mutex::new(HashMap::new()),
+            state_ttl: None,
+            seen_events: Mutex::new(SeenEvents {
+                capacity: DEFAULT_REMEMBERED_EVENTS,
+                ids: HashSet::new(),
+                order: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Forget conversations idle longer than ttl, checked on every message
+    pub fn state_ttl(mut self, ttl: Duration) -> Bot<S> {
+        self.state_ttl = Some(ttl);
+        self
+    }
+
+    /// Number of event ids handle_event remembers to skip redelivery, 0 disables it
+    pub fn remember_events(mut self, capacity: usize) -> Bot<S> {
+        self.seen_events = Mutex::new(SeenEvents {
+            capacity,
+            ids: HashSet::new(),
+            order: VecDeque::new(),
+        });
+        self
+    }
+
+    /// Route by metadata of quick_reply_response
+    pub fn on_quick_reply<F>(mut self, metadata: &str, handler: F) -> Bot<S>
+    where
+        F: Fn(&Incoming, &mut S) -> Option<MessageBuilder> + Send + Sync + 'static,
+    {
+        self.routes.push((
+            Matcher::QuickReply(String::from(metadata)),
+            Box::new(handler),
+        ));
+        self
+    }
+
+    /// Route by regex of text, checked in order of registration after quick replies
+    pub fn on_regex<F>(mut self, pattern: &str, handler: F) -> Result<Bot<S>, regex::Error>
+    where
+        F: Fn(&Incoming, &mut S) -> Option<MessageBuilder> + Send + Sync + 'static,
+    {
+        self.routes
+            .push((Matcher::Regex(Regex::new(pattern)?), Box::new(handler)));
+        Ok(self)
+    }
+
+    /// Called when no route matches
+    pub fn fallback<F>(mut self, handler: F) -> Bot<S>
+    where
+        F: Fn(&Incoming, &mut S) -> Option<MessageBuilder> + Send + Sync + 'static,
+    {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    pub fn user_id(&self) -> &str {
+        &self.user_id
+    }
+
+    /// Copy of the state of the conversation
+    pub fn state(&self, sender_id: &str) -> Option<S>
+    where
+        S: Clone,
+    {
+        self.lock_states().get(sender_id).map(|it| it.state.clone())
+    }
+
+    /// Forget the conversation
+    pub fn reset(&self, sender_id: &str) -> Option<S> {
+        self.lock_states().remove(sender_id).map(|it| it.state)
+    }
+
+    /// Forget conversations idle longer than idle, returns the number of them
+    pub fn evict_idle(&self, idle: Duration) -> usize {
+        let mut states = self.lock_states();
+        let before = states.len();
+        states.retain(|_, it| it.last_seen.elapsed() <= idle);
+        before - states.len()
+    }
+
+    pub fn conversations(&self) -> usize {
+        self.lock_states().len()
+    }
+
+    /// Run the matching handler and return its reply, None for the bot's own messages
+    pub fn route(&self, incoming: &Incoming) -> Option<MessageBuilder> {
+        if incoming.sender_id == self.user_id {
+            return None;
+        }
+        if let Some(ttl) = self.state_ttl {
+            self.evict_idle(ttl);
+        }
+        let mut states = self.lock_states();
+        let conversation =
+            states
+                .entry(incoming.sender_id.clone())
+                .or_insert_with(|| Conversation {
+                    state: S::default(),
+                    last_seen: Instant::now(),
+                });
+        conversation.last_seen = Instant::now();
+        let state = &mut conversation.state;
+        if let Some(ref metadata) = incoming.quick_reply_metadata {
+            for (matcher, handler) in self.routes.iter() {
+                if matches!(matcher, Matcher::QuickReply(it) if it == metadata) {
+                    return handler(incoming, state);
+                }
+            }
+        }
+        for (matcher, handler) in self.routes.iter() {
+            if let Matcher::Regex(regex) = matcher {
+                if let Some(captures) = regex.captures(&incoming.text) {
+                    let incoming = Incoming {
+                        captures: captures
+                            .iter()
+                            .map(|it| it.map(|it| String::from(it.as_str())).unwrap_or_default())
+                            .collect(),
+                        ..incoming.clone()
+                    };
+                    return handler(&incoming, state);
+                }
+            }
+        }
+        match self.fallback {
+            Some(ref fallback) => fallback(incoming, state),
+            None => None,
+        }
+    }
+
+    /// Route a direct message event and send the reply.
+    /// Events already handled are skipped, so redelivered webhooks are answered once,
+    /// even if sending the first reply failed.
+    pub async fn handle_event<T: Twapi + Sync + ?Sized>(
+        &self,
+        twapi: &T,
+        event: &DirectMessageEvent,
+        users: &HashMap<String, User>,
+    ) -> Result<Option<DirectMessageEvent>, TwapiError> {
+        let mut incoming = match Incoming::from_event(event) {
+            Some(incoming) => incoming,
+            None => return Ok(None),
+        };
+        if incoming.sender_id == self.user_id || !self.mark_seen(&incoming.event_id) {
+            return Ok(None);
+        }
+        incoming.sender = users.get(&incoming.sender_id).cloned();
+        match self.route(&incoming) {
+            Some(reply) => Ok(Some(reply.send(twapi).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Handle every direct_message_events of the webhook body for this bot
+    pub async fn handle_activity<T: Twapi + Sync + ?Sized>(
+        &self,
+        twapi: &T,
+        activity: &AccountActivity,
+    ) -> Vec<Result<Option<DirectMessageEvent>, TwapiError>> {
+        let mut results = vec![];
+        if activity.for_user_id != self.user_id {
+            return results;
+        }
+        for event in activity.events.iter() {
+            if let AccountActivityEvent::DirectMessage(event) = event {
+                results.push(self.handle_event(twapi, event, &activity.users).await);
+            }
+        }
+        results
+    }
+
+    fn lock_states(&self) -> std::sync::MutexGuard<'_, HashMap<String, Conversation<S>>> {
+        // state of a panicked handler is still usable
+        self.states
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn mark_seen(&self, event_id: &str) -> bool {
+        self.seen_events
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(event_id)
+    }
+}
+
+type ErrorCallback = Box<dyn Fn(&TwapiError) + Send + Sync>;
+
+/// AccountActivityHandler running Bot, to be given to WebhookHandler.
+/// Replies are sent before the webhook response, and Twitter redelivers the events
+/// if it is late; Bot::handle_event skips them.
+pub struct BotHandler<T, S> {
+    twapi: T,
+    bot: Bot<S>,
+    on_error: Option<ErrorCallback>,
+}
+
+impl<T, S> BotHandler<T, S> {
+    pub fn new(twapi: T, bot: Bot<S>) -> BotHandler<T, S> {
+        BotHandler {
+            twapi,
+            bot,
+            on_error: None,
+        }
+    }
+
+    /// Errors of sending replies, ignored if not set
+    pub fn on_error<F>(mut self, on_error: F) -> BotHandler<T, S>
+    where
+        F: Fn(&TwapiError) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Box::new(on_error));
+        self
+    }
+
+    pub fn bot(&self) -> &Bot<S> {
+        &self.bot
+    }
+
+    pub fn twapi(&self) -> &T {
+        &self.twapi
+    }
+}
+
+#[async_trait]
+impl<T, S> AccountActivityHandler for BotHandler<T, S>
+where
+    T: Twapi + Send + Sync,
+    S: Default + Send,
+{
+    async fn on_event(&self, activity: &AccountActivity, event: &AccountActivityEvent) {
+        let event = match event {
+            AccountActivityEvent::DirectMessage(event) => event,
+            _ => return,
+        };
+        if activity.for_user_id != self.bot.user_id {
+            return;
+        }
+        let result = self
+            .bot
+            .handle_event(&self.twapi, event, &activity.users)
+            .await;
+        if let (Err(err), Some(on_error)) = (result, self.on_error.as_ref()) {
+            on_error(&err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        account_activity::{calc_hmac, WebhookHandler},
+        mock::{MockResponse, MockTwapi},
+    };
+    use http::{Request, StatusCode};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    const SECRET: &str = "consumer_secret";
+    const BOT_ID: &str = "100";
+
+    fn body(events: serde_json::Value) -> String {
+        json!({
+            "for_user_id": BOT_ID,
+            "direct_message_events": events,
+            "users": {"200": {"id_str": "200", "screen_name": "alice"}}
+        })
+        .to_string()
+    }
+
+    fn message(id: &str, sender_id: &str, text: &str, metadata: Option<&str>) -> serde_json::Value {
+        let mut message_data = json!({"text": text});
+        if let Some(metadata) = metadata {
+            message_data["quick_reply_response"] = json!({"type": "options", "metadata": metadata});
+        }
+        json!({
+            "type": "message_create",
+            "id": id,
+            "created_timestamp": "1",
+            "message_create": {
+                "target": {"recipient_id": if sender_id == BOT_ID { "200" } else { BOT_ID }},
+                "sender_id": sender_id,
+                "message_data": message_data
+            }
+        })
+    }
+
+    fn post(body: &str) -> Request<String> {
+        Request::post("/webhook")
+            .header(
+                "x-twitter-webhooks-signature",
+                format!("sha256={}", calc_hmac(SECRET, body)),
+            )
+            .body(String::from(body))
+            .unwrap()
+    }
+
+    fn sent() -> MockResponse {
+        MockResponse::new(
+            200,
+            json!({"event": {"type": "message_create", "id": "900"}}),
+        )
+    }
+
+    fn bot() -> Bot<u32> {
+        Bot::new(BOT_ID)
+            .on_quick_reply("count", |incoming, count| {
+                *count += 1;
+                Some(incoming.reply(&format!("count {}", count)))
+            })
+            .on_regex(r"^hello (\w+)", |incoming, _| {
+                let name = incoming.sender.as_ref().map(|it| it.screen_name.clone());
+                Some(incoming.reply(&format!(
+                    "hi {} from {}",
+                    incoming.captures[1],
+                    name.unwrap_or_default()
+                )))
+            })
+            .unwrap()
+            .fallback(|incoming, _| Some(incoming.reply("?")))
+    }
+
+    fn sent_texts(twapi: &MockTwapi) -> Vec<(String, String)> {
+        twapi
+            .requests()
+            .iter()
+            .map(|it| {
+                assert_eq!(
+                    it.uri,
+                    "https://api.twitter.com/1.1/direct_messages/events/new.json"
+                );
+                let message_create = &it.json.as_ref().unwrap()["event"]["message_create"];
+                (
+                    String::from(message_create["target"]["recipient_id"].as_str().unwrap()),
+                    String::from(message_create["message_data"]["text"].as_str().unwrap()),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn replies_through_webhook_once() {
+        let twapi = MockTwapi::new();
+        twapi.push(sent());
+        let handler = WebhookHandler::new(SECRET, BotHandler::new(twapi, bot()));
+        let body = body(json!([message("1", "200", "hello world", None)]));
+        let response = handler.handle(&post(&body), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // redelivered
+        let response = handler.handle(&post(&body), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            sent_texts(handler.handler().twapi()),
+            vec![(String::from("200"), String::from("hi world from alice"))]
+        );
+    }
+
+    #[tokio::test]
+    async fn ignores_own_messages_and_other_users() {
+        let twapi = MockTwapi::new();
+        let handler = WebhookHandler::new(SECRET, BotHandler::new(twapi, bot()));
+        let body = body(json!([message("1", BOT_ID, "hello world", None)]));
+        handler.handle(&post(&body), None).await;
+        let body = body.replace(
+            &format!("\"for_user_id\":\"{}\"", BOT_ID),
+            "\"for_user_id\":\"300\"",
+        );
+        handler.handle(&post(&body), None).await;
+        assert!(handler.handler().twapi().requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn keeps_state_per_conversation() {
+        let twapi = MockTwapi::new();
+        for _ in 0..3 {
+            twapi.push(sent());
+        }
+        let bot = bot();
+        let events = [
+            message("1", "200", "", Some("count")),
+            message("2", "200", "hello x", Some("count")),
+            message("3", "201", "", Some("count")),
+        ];
+        for event in events.iter() {
+            let event: DirectMessageEvent = serde_json::from_value(event.clone()).unwrap();
+            bot.handle_event(&twapi, &event, &HashMap::new())
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        let texts: Vec<_> = sent_texts(&twapi).into_iter().map(|it| it.1).collect();
+        assert_eq!(texts, vec!["count 1", "count 2", "count 1"]);
+        assert_eq!(bot.state("200"), Some(2));
+        assert_eq!(bot.state("201"), Some(1));
+        assert_eq!(bot.reset("200"), Some(2));
+        assert_eq!(bot.state("200"), None);
+    }
+
+    #[tokio::test]
+    async fn reports_send_errors() {
+        let twapi = MockTwapi::new();
+        twapi.push(MockResponse::new(
+            403,
+            json!({"errors": [{"code": 349, "message": "You cannot send messages to this user."}]}),
+        ));
+        let errors = Arc::new(Mutex::new(vec![]));
+        let reported = errors.clone();
+        let bot_handler = BotHandler::new(twapi, bot()).on_error(move |err| {
+            reported.lock().unwrap().push(format!("{:?}", err));
+        });
+        let handler = WebhookHandler::new(SECRET, bot_handler);
+        let body = body(json!([message("1", "200", "hi", None)]));
+        let response = handler.handle(&post(&body), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let errors = errors.lock().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("349"), "{}", errors[0]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn evicts_idle_conversations() {
+        let bot = bot().state_ttl(Duration::from_secs(60));
+        let incoming = |sender_id: &str| Incoming {
+            event_id: String::new(),
+            sender_id: String::from(sender_id),
+            text: String::new(),
+            quick_reply_metadata: Some(String::from("count")),
+            sender: None,
+            captures: vec![],
+        };
+        bot.route(&incoming("200"));
+        tokio::time::advance(Duration::from_secs(30)).await;
+        bot.route(&incoming("201"));
+        tokio::time::advance(Duration::from_secs(31)).await;
+        bot.route(&incoming("201"));
+        assert_eq!(bot.state("200"), None);
+        assert_eq!(bot.state("201"), Some(2));
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(bot.evict_idle(Duration::from_secs(5)), 1);
+        assert_eq!(bot.conversations(), 0);
+    }
+
+    #[test]
+    fn forgets_oldest_event_ids() {
+        let mut seen = SeenEvents {
+            capacity: 2,
+            ids: HashSet::new(),
+            order: VecDeque::new(),
+        };
+        assert!(seen.insert("1"));
+        assert!(seen.insert("2"));
+        assert!(!seen.insert("1"));
+        assert!(seen.insert("3"));
+        assert!(seen.insert("1"));
+        assert!(seen.insert(""));
+        assert!(seen.insert(""));
+    }
+}
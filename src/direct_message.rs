@@ -0,0 +1,393 @@
+//! Direct message events with quick replies, CTAs and media
+use super::{
+    media::{media_id_of, MediaUploader, MediaUsage},
+    models::{
+        Attachment, AttachmentMedia, Cta, DirectMessageEvent, MessageCreate, MessageData,
+        MessageTarget, QuickReply, QuickReplyOption, TwapiTyped,
+    },
+    pagination, Twapi, TwapiError,
+};
+use futures_util::stream::{Stream, StreamExt};
+use serde_json::json;
+
+/// Maximum characters of text
+pub const MAX_TEXT_LENGTH: usize = 10000;
+
+/// Maximum options of quick_reply
+pub const MAX_QUICK_REPLY_OPTIONS: usize = 20;
+
+/// Maximum characters of quick reply label and CTA label
+pub const MAX_LABEL_LENGTH: usize = 36;
+
+/// Maximum characters of quick reply description
+pub const MAX_DESCRIPTION_LENGTH: usize = 72;
+
+/// Maximum characters of quick reply metadata
+pub const MAX_METADATA_LENGTH: usize = 1000;
+
+/// Maximum CTAs of one message
+pub const MAX_CTAS: usize = 3;
+
+/// Invalid message of MessageBuilder
+#[derive(Debug, Clone, PartialEq)]
+pub enum DirectMessageError {
+    /// No text nor media
+    Empty,
+    TextTooLong(usize),
+    TooManyQuickReplyOptions(usize),
+    TooManyCtas(usize),
+    /// Field name and characters of a label, description or metadata
+    FieldTooLong {
+        field: String,
+        length: usize,
+    },
+    /// media_path is set, to_value needs it uploaded as media_id
+    MediaNotUploaded,
+}
+
+impl From<DirectMessageError> for TwapiError {
+    fn from(err: DirectMessageError) -> TwapiError {
+        TwapiError::DirectMessage(err)
+    }
+}
+
+/// Builder of message_create event for direct_messages/events/new
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageBuilder {
+    recipient_id: String,
+    message_data: MessageData,
+    media_path: Option<String>,
+}
+
+impl MessageBuilder {
+    pub fn new(recipient_id: &str, text: &str) -> MessageBuilder {
+        MessageBuilder {
+            recipient_id: String::from(recipient_id),
+            message_data: MessageData {
+                text: String::from(text),
+                ..Default::default()
+            },
+            media_path: None,
+        }
+    }
+
+    /// Option of quick_reply, metadata is returned in quick_reply_response
+    pub fn quick_reply_option(
+        mut self,
+        label: &str,
+        description: Option<&str>,
+        metadata: Option<&str>,
+    ) -> MessageBuilder {
+        let quick_reply = self
+            .message_data
+            .quick_reply
+            .get_or_insert_with(|| QuickReply {
+                quick_reply_type: String::from("options"),
+                options: vec![],
+            });
+        quick_reply.options.push(QuickReplyOption {
+            label: String::from(label),
+            description: description.map(String::from),
+            metadata: metadata.map(String::from),
+        });
+        self
+    }
+
+    /// web_url button
+    pub fn cta(mut self, label: &str, url: &str) -> MessageBuilder {
+        self.message_data
+            .ctas
+            .get_or_insert_with(Vec::new)
+            .push(Cta {
+                cta_type: String::from("web_url"),
+                label: String::from(label),
+                url: String::from(url),
+            });
+        self
+    }
+
+    /// Already uploaded dm_image, dm_gif or dm_video
+    pub fn media_id(mut self, media_id: &str) -> MessageBuilder {
+        self.message_data.attachment = Some(Attachment {
+            attachment_type: String::from("media"),
+            media: AttachmentMedia {
+                id: String::from(media_id),
+                ..Default::default()
+            },
+        });
+        self.media_path = None;
+        self
+    }
+
+    /// Uploaded by MediaUploader for direct message before sending
+    pub fn media_path(mut self, path: &str) -> MessageBuilder {
+        self.message_data.attachment = None;
+        self.media_path = Some(String::from(path));
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), DirectMessageError> {
+        let data = &self.message_data;
+        if data.text.is_empty() && data.attachment.is_none() && self.media_path.is_none() {
+            return Err(DirectMessageError::Empty);
+        }
+        let length = data.text.chars().count();
+        if length > MAX_TEXT_LENGTH {
+            return Err(DirectMessageError::TextTooLong(length));
+        }
+        if let Some(ref quick_reply) = data.quick_reply {
+            if quick_reply.options.len() > MAX_QUICK_REPLY_OPTIONS {
+                return Err(DirectMessageError::TooManyQuickReplyOptions(
+                    quick_reply.options.len(),
+                ));
+            }
+            for option in quick_reply.options.iter() {
+                check_length("label", &option.label, MAX_LABEL_LENGTH)?;
+                if let Some(ref description) = option.description {
+                    check_length("description", description, MAX_DESCRIPTION_LENGTH)?;
+                }
+                if let Some(ref metadata) = option.metadata {
+                    check_length("metadata", metadata, MAX_METADATA_LENGTH)?;
+                }
+            }
+        }
+        if let Some(ref ctas) = data.ctas {
+            if ctas.len() > MAX_CTAS {
+                return Err(DirectMessageError::TooManyCtas(ctas.len()));
+            }
+            for cta in ctas.iter() {
+                check_length("cta label", &cta.label, MAX_LABEL_LENGTH)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Body of direct_messages/events/new, media_path must be uploaded already
+    pub fn to_value(&self) -> Result<serde_json::Value, TwapiError> {
+        self.validate()?;
+        if self.media_path.is_some() {
+            return Err(DirectMessageError::MediaNotUploaded.into());
+        }
+        let message_create = MessageCreate {
+            target: MessageTarget {
+                recipient_id: self.recipient_id.clone(),
+            },
+            message_data: self.message_data.clone(),
+            ..Default::default()
+        };
+        Ok(json!({
+            "event": {
+                "type": "message_create",
+                "message_create": message_create,
+            }
+        }))
+    }
+
+    /// Validate, upload media_path and send
+    pub async fn send<T: Twapi + Sync + ?Sized>(
+        &self,
+        twapi: &T,
+    ) -> Result<DirectMessageEvent, TwapiError> {
+        self.validate()?;
+        let message = match self.media_path {
+            Some(ref path) => {
+                let result = MediaUploader::auto(MediaUsage::DirectMessage)
+                    .upload(twapi, path)
+                    .await?;
+                self.clone().media_id(&media_id_of(&result)?)
+            }
+            None => self.clone(),
+        };
+        twapi
+            .post_direct_messages_events_new_typed(&message.to_value()?)
+            .await
+    }
+}
+
+fn check_length(field: &str, value: &str, max_length: usize) -> Result<(), DirectMessageError> {
+    let length = value.chars().count();
+    if length > max_length {
+        Err(DirectMessageError::FieldTooLong {
+            field: String::from(field),
+            length,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// Typed direct_messages/events/list of the last 30 days, newest first
+pub fn events_stream<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    params: &[(&str, &str)],
+    max_pages: Option<usize>,
+) -> impl Stream<Item = Result<DirectMessageEvent, TwapiError>> + Send + 'a {
+    pagination::direct_messages_events_stream(twapi, params, max_pages)
+        .map(|item| Ok(serde_json::from_value(item?)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockResponse, MockTwapi};
+
+    fn too_long(length: usize) -> String {
+        "a".repeat(length + 1)
+    }
+
+    #[test]
+    fn rejects_empty_and_too_long_text() {
+        assert_eq!(
+            MessageBuilder::new("1", "").validate(),
+            Err(DirectMessageError::Empty)
+        );
+        assert_eq!(
+            MessageBuilder::new("1", "").media_id("m").validate(),
+            Ok(())
+        );
+        assert_eq!(
+            MessageBuilder::new("1", &too_long(MAX_TEXT_LENGTH)).validate(),
+            Err(DirectMessageError::TextTooLong(MAX_TEXT_LENGTH + 1))
+        );
+    }
+
+    #[test]
+    fn limits_quick_reply_options() {
+        let builder = (0..MAX_QUICK_REPLY_OPTIONS)
+            .fold(MessageBuilder::new("1", "pick"), |it, i| {
+                it.quick_reply_option(&i.to_string(), None, None)
+            });
+        assert_eq!(builder.validate(), Ok(()));
+        assert_eq!(
+            builder.quick_reply_option("more", None, None).validate(),
+            Err(DirectMessageError::TooManyQuickReplyOptions(
+                MAX_QUICK_REPLY_OPTIONS + 1
+            ))
+        );
+    }
+
+    #[test]
+    fn limits_ctas() {
+        let builder = (0..MAX_CTAS).fold(MessageBuilder::new("1", "links"), |it, i| {
+            it.cta(&i.to_string(), "https://example.com")
+        });
+        assert_eq!(builder.validate(), Ok(()));
+        assert_eq!(
+            builder.cta("more", "https://example.com").validate(),
+            Err(DirectMessageError::TooManyCtas(MAX_CTAS + 1))
+        );
+    }
+
+    #[test]
+    fn limits_field_lengths() {
+        let cases = vec![
+            (
+                MessageBuilder::new("1", "a").quick_reply_option(
+                    &too_long(MAX_LABEL_LENGTH),
+                    None,
+                    None,
+                ),
+                "label",
+                MAX_LABEL_LENGTH,
+            ),
+            (
+                MessageBuilder::new("1", "a").quick_reply_option(
+                    "ok",
+                    Some(&too_long(MAX_DESCRIPTION_LENGTH)),
+                    None,
+                ),
+                "description",
+                MAX_DESCRIPTION_LENGTH,
+            ),
+            (
+                MessageBuilder::new("1", "a").quick_reply_option(
+                    "ok",
+                    None,
+                    Some(&too_long(MAX_METADATA_LENGTH)),
+                ),
+                "metadata",
+                MAX_METADATA_LENGTH,
+            ),
+            (
+                MessageBuilder::new("1", "a")
+                    .cta(&too_long(MAX_LABEL_LENGTH), "https://example.com"),
+                "cta label",
+                MAX_LABEL_LENGTH,
+            ),
+        ];
+        for (builder, field, max_length) in cases {
+            assert_eq!(
+                builder.validate(),
+                Err(DirectMessageError::FieldTooLong {
+                    field: String::from(field),
+                    length: max_length + 1,
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn requires_media_path_uploaded_for_to_value() {
+        let builder = MessageBuilder::new("1", "photo").media_path("photo.png");
+        assert_eq!(builder.validate(), Ok(()));
+        match builder.to_value() {
+            Err(TwapiError::DirectMessage(DirectMessageError::MediaNotUploaded)) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+        assert!(builder.media_id("m1").to_value().is_ok());
+    }
+
+    #[test]
+    fn builds_message_create_event() {
+        let value = MessageBuilder::new("200", "hello")
+            .quick_reply_option("Yes", Some("sure"), Some("yes"))
+            .quick_reply_option("No", None, None)
+            .cta("Site", "https://example.com")
+            .media_id("m1")
+            .to_value()
+            .unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "event": {
+                    "type": "message_create",
+                    "message_create": {
+                        "target": {"recipient_id": "200"},
+                        "message_data": {
+                            "text": "hello",
+                            "quick_reply": {
+                                "type": "options",
+                                "options": [
+                                    {"label": "Yes", "description": "sure", "metadata": "yes"},
+                                    {"label": "No"}
+                                ]
+                            },
+                            "attachment": {"type": "media", "media": {"id": "m1"}},
+                            "ctas": [{"type": "web_url", "label": "Site", "url": "https://example.com"}]
+                        }
+                    }
+                }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_event() {
+        let twapi = MockTwapi::new();
+        twapi.push(MockResponse::new(
+            200,
+            json!({"event": {"type": "message_create", "id": "e1"}}),
+        ));
+        let builder = MessageBuilder::new("200", "hello");
+        let event = builder.send(&twapi).await.unwrap();
+        assert_eq!(event.id, "e1");
+        let requests = twapi.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "JSON");
+        assert_eq!(
+            requests[0].uri,
+            "https://api.twitter.com/1.1/direct_messages/events/new.json"
+        );
+        assert_eq!(requests[0].json, Some(builder.to_value().unwrap()));
+    }
+}
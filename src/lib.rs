@@ -1,39 +1,99 @@
 //! A simple Twitter library. This is easy for customize.
 use async_trait::async_trait;
-use std::{
-    io::{BufReader, Cursor, Read},
-    time,
-};
+use std::time;
 use twapi_reqwest::reqwest::{
+    header::HeaderMap,
     multipart::{Form, Part},
     Error, Response,
 };
 
+pub mod direct_message;
+pub mod media;
+pub mod media_metadata;
+pub mod models;
 pub mod oauth1;
 pub mod oauth2;
+pub mod pagination;
+pub mod render;
+pub mod retry;
+pub mod text;
+pub mod tweet;
+pub mod v2;
+pub mod welcome_message;
 
 #[cfg(feature = "account-activity")]
 pub mod account_activity;
 
+#[cfg(feature = "account-activity")]
+pub mod webhook_manager;
+
+#[cfg(feature = "chatbot")]
+pub mod chatbot;
+
+#[cfg(test)]
+mod mock;
+
 type TResult = Result<Response, Error>;
 
+/// Default base url of Twitter API
+pub const API_BASE_URL: &str = "https://api.twitter.com";
+
+/// Default base url of Twitter media upload API
+pub const UPLOAD_BASE_URL: &str = "https://upload.twitter.com";
+
+/// x-rate-limit-* headers of Twitter API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub limit: u64,
+    pub remaining: u64,
+    /// UTC epoch seconds
+    pub reset: u64,
+}
+
+impl RateLimit {
+    pub fn from_headers(headers: &HeaderMap) -> Option<RateLimit> {
+        let get = |name: &str| -> Option<u64> { headers.get(name)?.to_str().ok()?.parse().ok() };
+        Some(RateLimit {
+            limit: get("x-rate-limit-limit")?,
+            remaining: get("x-rate-limit-remaining")?,
+            reset: get("x-rate-limit-reset")?,
+        })
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining == 0
+    }
+
+    /// Duration until the window resets, zero if already reset
+    pub fn reset_after(&self) -> time::Duration {
+        let now = time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        time::Duration::from_secs(self.reset.saturating_sub(now))
+    }
+}
+
 /// Response from Twitter API
 #[derive(Debug)]
 pub struct TwapiResponse {
     pub status_code: u16,
     pub json: Option<serde_json::Value>,
+    pub headers: HeaderMap,
+    pub rate_limit: Option<RateLimit>,
 }
 
 impl TwapiResponse {
     pub async fn new(response: Response) -> TwapiResponse {
         let status_code = response.status().as_u16();
-        let json = match response.json().await {
-            Ok(json) => Some(json),
-            Err(_) => None,
-        };
+        let headers = response.headers().clone();
+        let rate_limit = RateLimit::from_headers(&headers);
+        let json = response.json().await.ok();
         TwapiResponse {
-            status_code: status_code,
-            json: json,
+            status_code,
+            json,
+            headers,
+            rate_limit,
         }
     }
 
@@ -42,11 +102,155 @@ impl TwapiResponse {
     }
 
     pub fn copy_json_value(&self) -> Option<serde_json::Value> {
-        match &self.json {
-            Some(ref json) => Some(json.clone()),
-            None => None,
+        self.json.clone()
+    }
+
+    /// Parsed error body when the response is not success
+    pub fn error(&self) -> Option<ApiError> {
+        if self.is_success() {
+            None
+        } else {
+            Some(ApiError::new(self.status_code, self.json.as_ref()))
+        }
+    }
+
+    /// Turn non-2xx response into TwapiError::Api
+    pub fn into_result(self) -> Result<TwapiResponse, TwapiError> {
+        match self.error() {
+            Some(err) => Err(TwapiError::Api(err)),
+            None => Ok(self),
+        }
+    }
+
+    /// Deserialize success json into T, non-2xx response into TwapiError::Api
+    pub fn parse<T: serde::de::DeserializeOwned>(self) -> Result<T, TwapiError> {
+        let json = self.into_result()?.json.ok_or(TwapiError::NotExists)?;
+        Ok(serde_json::from_value(json)?)
+    }
+}
+
+/// Known error codes of Twitter API
+/// https://developer.twitter.com/en/support/twitter-api/error-troubleshooting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiErrorKind {
+    AuthenticationFailed,
+    NotFound,
+    UserNotFound,
+    UserSuspended,
+    Suspended,
+    RateLimited,
+    InvalidToken,
+    OverCapacity,
+    InternalError,
+    StatusNotFound,
+    DailyStatusLimit,
+    TweetTooLong,
+    DuplicateStatus,
+    BadAuthenticationData,
+    AccountLocked,
+    CannotSendMessage,
+    Other(u64),
+}
+
+impl From<u64> for ApiErrorKind {
+    fn from(code: u64) -> ApiErrorKind {
+        match code {
+            32 => ApiErrorKind::AuthenticationFailed,
+            34 => ApiErrorKind::NotFound,
+            50 => ApiErrorKind::UserNotFound,
+            63 => ApiErrorKind::UserSuspended,
+            64 => ApiErrorKind::Suspended,
+            88 => ApiErrorKind::RateLimited,
+            89 => ApiErrorKind::InvalidToken,
+            130 => ApiErrorKind::OverCapacity,
+            131 => ApiErrorKind::InternalError,
+            144 => ApiErrorKind::StatusNotFound,
+            185 => ApiErrorKind::DailyStatusLimit,
+            186 => ApiErrorKind::TweetTooLong,
+            187 => ApiErrorKind::DuplicateStatus,
+            215 => ApiErrorKind::BadAuthenticationData,
+            326 => ApiErrorKind::AccountLocked,
+            349 => ApiErrorKind::CannotSendMessage,
+            _ => ApiErrorKind::Other(code),
+        }
+    }
+}
+
+/// One entry of {"errors":[{"code":..,"message":..}]}
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiErrorDetail {
+    pub code: Option<u64>,
+    pub message: String,
+}
+
+impl ApiErrorDetail {
+    pub fn kind(&self) -> Option<ApiErrorKind> {
+        self.code.map(ApiErrorKind::from)
+    }
+}
+
+/// Error body of Twitter API
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    pub status_code: u16,
+    pub errors: Vec<ApiErrorDetail>,
+    pub json: Option<serde_json::Value>,
+}
+
+impl ApiError {
+    pub fn new(status_code: u16, json: Option<&serde_json::Value>) -> ApiError {
+        let errors = match json {
+            Some(json) => parse_error_details(json),
+            None => vec![],
+        };
+        ApiError {
+            status_code,
+            errors,
+            json: json.cloned(),
         }
     }
+
+    pub fn kinds(&self) -> Vec<ApiErrorKind> {
+        self.errors.iter().filter_map(|it| it.kind()).collect()
+    }
+
+    pub fn has(&self, kind: ApiErrorKind) -> bool {
+        self.kinds().contains(&kind)
+    }
+
+    pub fn is_rate_limited(&self) -> bool {
+        self.status_code == 429 || self.has(ApiErrorKind::RateLimited)
+    }
+}
+
+// v1.1 uses {"errors":[{"code","message"}]} and sometimes {"error":".."},
+// v2 uses {"errors":[{"message"}]} or {"title","detail"}.
+fn parse_error_details(json: &serde_json::Value) -> Vec<ApiErrorDetail> {
+    if let Some(errors) = json.get("errors").and_then(|it| it.as_array()) {
+        return errors
+            .iter()
+            .map(|it| ApiErrorDetail {
+                code: it.get("code").and_then(|it| it.as_u64()),
+                message: it
+                    .get("message")
+                    .or_else(|| it.get("detail"))
+                    .and_then(|it| it.as_str())
+                    .unwrap_or_default()
+                    .to_string(),
+            })
+            .collect();
+    }
+    json.get("error")
+        .or_else(|| json.get("detail"))
+        .or_else(|| json.get("title"))
+        .and_then(|it| it.as_str())
+        .map(|message| {
+            vec![ApiErrorDetail {
+                code: None,
+                message: message.to_string(),
+            }]
+        })
+        .unwrap_or_default()
 }
 
 /// Error in twapi library
@@ -57,6 +261,11 @@ pub enum TwapiError {
     Token((u16, String)),
     UrlParse(url::ParseError),
     NotExists,
+    Api(ApiError),
+    Json(serde_json::Error),
+    Media(media::MediaError),
+    Tweet(tweet::TweetError),
+    DirectMessage(direct_message::DirectMessageError),
 }
 
 impl From<Error> for TwapiError {
@@ -77,20 +286,24 @@ impl From<url::ParseError> for TwapiError {
     }
 }
 
+impl From<serde_json::Error> for TwapiError {
+    fn from(err: serde_json::Error) -> TwapiError {
+        TwapiError::Json(err)
+    }
+}
+
 fn make_account_activity_uri(
+    base_url: &str,
     command_type: &str,
     env_name: Option<&str>,
     file_name: Option<&str>,
 ) -> String {
     let prefix = match env_name {
         Some(env_name) => format!(
-            "https://api.twitter.com/1.1/account_activity/all/{}/{}",
-            env_name, command_type
-        ),
-        None => format!(
-            "https://api.twitter.com/1.1/account_activity/{}",
-            command_type
+            "{}/1.1/account_activity/all/{}/{}",
+            base_url, env_name, command_type
         ),
+        None => format!("{}/1.1/account_activity/{}", base_url, command_type),
     };
     match file_name {
         Some(file_name) => format!("{}/{}.json", prefix, file_name),
@@ -98,7 +311,12 @@ fn make_account_activity_uri(
     }
 }
 
+fn trim_base_url(base_url: &str) -> String {
+    String::from(base_url.trim_end_matches('/'))
+}
+
 /// Access to Twitter API
+#[allow(clippy::ptr_arg)]
 #[async_trait]
 pub trait Twapi {
     async fn get(&self, uri: &str, query_options: &Vec<(&str, &str)>) -> TResult;
@@ -122,13 +340,36 @@ pub trait Twapi {
         json: &serde_json::Value,
     ) -> TResult;
 
+    /// PUT with JSON body, 501 Not Implemented unless the implementation overrides it
+    async fn put_json(
+        &self,
+        _uri: &str,
+        _query_options: &Vec<(&str, &str)>,
+        _json: &serde_json::Value,
+    ) -> TResult {
+        Ok(not_implemented_response("put_json"))
+    }
+
+    /// Base url used by the built-in api.twitter.com endpoints
+    fn api_base_url(&self) -> &str {
+        API_BASE_URL
+    }
+
+    /// Base url used by the built-in upload.twitter.com endpoints
+    fn upload_base_url(&self) -> &str {
+        UPLOAD_BASE_URL
+    }
+
     async fn get_verify_credentials(
         &self,
         params: &Vec<(&str, &str)>,
     ) -> Result<TwapiResponse, TwapiError> {
         let res = self
             .get(
-                "https://api.twitter.com/1.1/account/verify_credentials.json",
+                &format!(
+                    "{}/1.1/account/verify_credentials.json",
+                    self.api_base_url()
+                ),
                 params,
             )
             .await?;
@@ -140,7 +381,10 @@ pub trait Twapi {
         params: &Vec<(&str, &str)>,
     ) -> Result<TwapiResponse, TwapiError> {
         let res = self
-            .get("https://api.twitter.com/1.1/search/tweets.json", params)
+            .get(
+                &format!("{}/1.1/search/tweets.json", self.api_base_url()),
+                params,
+            )
             .await?;
         Ok(TwapiResponse::new(res).await)
     }
@@ -151,7 +395,7 @@ pub trait Twapi {
     ) -> Result<TwapiResponse, TwapiError> {
         let res = self
             .post(
-                "https://api.twitter.com/1.1/statuses/update.json",
+                &format!("{}/1.1/statuses/update.json", self.api_base_url()),
                 &vec![],
                 params,
             )
@@ -159,13 +403,27 @@ pub trait Twapi {
         Ok(TwapiResponse::new(res).await)
     }
 
+    async fn post_statuses_destroy(&self, id: &str) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .post(
+                &format!("{}/1.1/statuses/destroy/{}.json", self.api_base_url(), id),
+                &vec![],
+                &vec![],
+            )
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
+
     async fn post_direct_messages_events_new(
         &self,
         value: &serde_json::Value,
     ) -> Result<TwapiResponse, TwapiError> {
         let res = self
             .json(
-                "https://api.twitter.com/1.1/direct_messages/events/new.json",
+                &format!(
+                    "{}/1.1/direct_messages/events/new.json",
+                    self.api_base_url()
+                ),
                 &vec![],
                 value,
             )
@@ -173,6 +431,86 @@ pub trait Twapi {
         Ok(TwapiResponse::new(res).await)
     }
 
+    async fn get_direct_messages_events_list(
+        &self,
+        params: &Vec<(&str, &str)>,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .get(
+                &format!(
+                    "{}/1.1/direct_messages/events/list.json",
+                    self.api_base_url()
+                ),
+                params,
+            )
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
+
+    async fn get_direct_messages_events_show(&self, id: &str) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .get(
+                &format!(
+                    "{}/1.1/direct_messages/events/show.json",
+                    self.api_base_url()
+                ),
+                &vec![("id", id)],
+            )
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
+
+    async fn delete_direct_messages_events_destroy(
+        &self,
+        id: &str,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .delete(
+                &format!(
+                    "{}/1.1/direct_messages/events/destroy.json",
+                    self.api_base_url()
+                ),
+                &vec![("id", id)],
+            )
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
+
+    async fn post_direct_messages_indicate_typing(
+        &self,
+        recipient_id: &str,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .post(
+                &format!(
+                    "{}/1.1/direct_messages/indicate_typing.json",
+                    self.api_base_url()
+                ),
+                &vec![],
+                &vec![("recipient_id", recipient_id)],
+            )
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
+
+    async fn post_direct_messages_mark_read(
+        &self,
+        last_read_event_id: &str,
+        recipient_id: &str,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .post(
+                &format!("{}/1.1/direct_messages/mark_read.json", self.api_base_url()),
+                &vec![],
+                &vec![
+                    ("last_read_event_id", last_read_event_id),
+                    ("recipient_id", recipient_id),
+                ],
+            )
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
+
     async fn get_account_activity_subscription(
         &self,
         env_name: &str,
@@ -180,7 +518,8 @@ pub trait Twapi {
         let res = self
             .get(
                 format!(
-                    "https://api.twitter.com/1.1/account_activity/all/{}/subscriptions.json",
+                    "{}/1.1/account_activity/all/{}/subscriptions.json",
+                    self.api_base_url(),
                     env_name
                 )
                 .as_str(),
@@ -193,7 +532,10 @@ pub trait Twapi {
     async fn get_direct_messages_welcome_messages_list(&self) -> Result<TwapiResponse, TwapiError> {
         let res = self
             .get(
-                "https://api.twitter.com/1.1/direct_messages/welcome_messages/list.json",
+                &format!(
+                    "{}/1.1/direct_messages/welcome_messages/list.json",
+                    self.api_base_url()
+                ),
                 &vec![],
             )
             .await?;
@@ -206,7 +548,10 @@ pub trait Twapi {
     ) -> Result<TwapiResponse, TwapiError> {
         let res = self
             .get(
-                "https://api.twitter.com/1.1/direct_messages/welcome_messages/show.json",
+                &format!(
+                    "{}/1.1/direct_messages/welcome_messages/show.json",
+                    self.api_base_url()
+                ),
                 &vec![("id", id)],
             )
             .await?;
@@ -219,7 +564,10 @@ pub trait Twapi {
     ) -> Result<TwapiResponse, TwapiError> {
         let res = self
             .json(
-                "https://api.twitter.com/1.1/direct_messages/welcome_messages/new.json",
+                &format!(
+                    "{}/1.1/direct_messages/welcome_messages/new.json",
+                    self.api_base_url()
+                ),
                 &vec![],
                 value,
             )
@@ -233,7 +581,98 @@ pub trait Twapi {
     ) -> Result<TwapiResponse, TwapiError> {
         let res = self
             .delete(
-                "https://api.twitter.com/1.1/direct_messages/welcome_messages/destroy.json",
+                &format!(
+                    "{}/1.1/direct_messages/welcome_messages/destroy.json",
+                    self.api_base_url()
+                ),
+                &vec![("id", id)],
+            )
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
+
+    async fn put_direct_messages_welcome_messages_update(
+        &self,
+        id: &str,
+        value: &serde_json::Value,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .put_json(
+                &format!(
+                    "{}/1.1/direct_messages/welcome_messages/update.json",
+                    self.api_base_url()
+                ),
+                &vec![("id", id)],
+                value,
+            )
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
+
+    async fn post_direct_messages_welcome_messages_rules_new(
+        &self,
+        welcome_message_id: &str,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let value = serde_json::json!({
+            "welcome_message_rule": {
+                "welcome_message_id": welcome_message_id,
+            }
+        });
+        let res = self
+            .json(
+                &format!(
+                    "{}/1.1/direct_messages/welcome_messages/rules/new.json",
+                    self.api_base_url()
+                ),
+                &vec![],
+                &value,
+            )
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
+
+    async fn get_direct_messages_welcome_messages_rules_list(
+        &self,
+        params: &Vec<(&str, &str)>,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .get(
+                &format!(
+                    "{}/1.1/direct_messages/welcome_messages/rules/list.json",
+                    self.api_base_url()
+                ),
+                params,
+            )
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
+
+    async fn get_direct_messages_welcome_messages_rules_show(
+        &self,
+        id: &str,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .get(
+                &format!(
+                    "{}/1.1/direct_messages/welcome_messages/rules/show.json",
+                    self.api_base_url()
+                ),
+                &vec![("id", id)],
+            )
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
+
+    async fn delete_direct_messages_welcome_messages_rules_destroy(
+        &self,
+        id: &str,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .delete(
+                &format!(
+                    "{}/1.1/direct_messages/welcome_messages/rules/destroy.json",
+                    self.api_base_url()
+                ),
                 &vec![("id", id)],
             )
             .await?;
@@ -243,7 +682,7 @@ pub trait Twapi {
     async fn get_media_upload(&self, media_id: &str) -> Result<TwapiResponse, TwapiError> {
         let res = self
             .get(
-                "https://upload.twitter.com/1.1/media/upload.json",
+                &format!("{}/1.1/media/upload.json", self.upload_base_url()),
                 &vec![("command", "STATUS"), ("media_id", media_id)],
             )
             .await?;
@@ -254,25 +693,38 @@ pub trait Twapi {
         &self,
         media_id: &str,
     ) -> Result<TwapiResponse, TwapiError> {
+        self.get_media_upload_until_succeeded_with(media_id, &media::PollOptions::default())
+            .await
+    }
+
+    /// Poll STATUS until succeeded.
+    /// Failed state, malformed response and exceeding options are TwapiError::Media.
+    async fn get_media_upload_until_succeeded_with(
+        &self,
+        media_id: &str,
+        options: &media::PollOptions,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let started = tokio::time::Instant::now();
+        let mut polls = 0;
         loop {
-            let check_after_secs = {
-                let result = self.get_media_upload(&media_id).await?;
-                if !result.is_success() {
-                    return Ok(result);
-                }
-                let json = result.copy_json_value().unwrap();
-                let processing_info = json.get("processing_info").unwrap();
-                let state = String::from(processing_info.get("state").unwrap().as_str().unwrap());
-                if state == "succeeded" || state == "failed" {
-                    return Ok(result);
-                }
-                processing_info
-                    .get("check_after_secs")
-                    .unwrap()
-                    .as_u64()
-                    .unwrap()
-            };
-            tokio::time::sleep(time::Duration::new(check_after_secs, 0)).await;
+            let result = self.get_media_upload(media_id).await?;
+            polls += 1;
+            if !result.is_success() {
+                return Ok(result);
+            }
+            let check_after_secs =
+                match media::check_processing_info(media_id, result.json.as_ref())? {
+                    Some(check_after_secs) => check_after_secs,
+                    None => return Ok(result),
+                };
+            let wait = time::Duration::from_secs(check_after_secs);
+            let over_polls = matches!(options.max_polls, Some(max_polls) if polls >= max_polls);
+            let over_deadline =
+                matches!(options.deadline, Some(deadline) if started.elapsed() + wait > deadline);
+            if over_polls || over_deadline {
+                return Err(media::MediaError::Timeout(String::from(media_id)).into());
+            }
+            tokio::time::sleep(wait).await;
         }
     }
 
@@ -281,14 +733,16 @@ pub trait Twapi {
         file: &str,
         additional_owners: Option<String>,
     ) -> Result<TwapiResponse, TwapiError> {
-        let metadata = std::fs::metadata(file).unwrap();
-        let file_size = metadata.len();
-        let f = std::fs::File::open(file).unwrap();
-        let mut cursor = Cursor::new(vec![0; file_size as usize]);
-        let mut reader = BufReader::new(f);
-        reader.read(cursor.get_mut()).unwrap();
+        let data = tokio::fs::read(file).await?;
+        self.post_media_upload_bytes(data, additional_owners).await
+    }
 
-        let part = Part::bytes(cursor.into_inner());
+    async fn post_media_upload_bytes(
+        &self,
+        data: Vec<u8>,
+        additional_owners: Option<String>,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let part = Part::bytes(data);
         let form = Form::new().part("media", part);
         let form = if let Some(additional_owners) = additional_owners {
             form.text("additional_owners", additional_owners)
@@ -297,7 +751,7 @@ pub trait Twapi {
         };
         let res = self
             .multipart(
-                "https://upload.twitter.com/1.1/media/upload.json",
+                &format!("{}/1.1/media/upload.json", self.upload_base_url()),
                 &vec![],
                 form,
             )
@@ -305,6 +759,7 @@ pub trait Twapi {
         Ok(TwapiResponse::new(res).await)
     }
 
+    /// Chunked upload by MediaUploader, non-2xx response is returned as Ok
     async fn post_media_upload_chunk(
         &self,
         file: &str,
@@ -312,105 +767,57 @@ pub trait Twapi {
         media_category: &str,
         additional_owners: Option<String>,
     ) -> Result<TwapiResponse, TwapiError> {
-        let metadata = std::fs::metadata(file)?;
-        let file_size = metadata.len();
-        let form = Form::new()
-            .text("command", "INIT")
-            .text("total_bytes", file_size.to_string())
-            .text("media_type", String::from(media_type))
-            .text("media_category", String::from(media_category));
-        let form = if let Some(additional_owners) = additional_owners {
-            form.text("additional_owners", additional_owners)
-        } else {
-            form
-        };
-        let media_id = {
-            let response = self
-                .multipart(
-                    "https://upload.twitter.com/1.1/media/upload.json",
-                    &vec![],
-                    form,
-                )
-                .await?;
-            let result = TwapiResponse::new(response).await;
-            if !result.is_success() {
-                return Ok(result);
-            }
-            String::from(
-                result
-                    .json
-                    .unwrap()
-                    .get("media_id_string")
-                    .unwrap()
-                    .as_str()
-                    .unwrap(),
-            )
-        };
+        let uploader = chunk_uploader(media_type, media_category, additional_owners);
+        api_error_as_response(uploader.upload(self, file).await)
+    }
 
-        let mut segment_index = 0;
-        let f = std::fs::File::open(file)?;
-        let mut reader = BufReader::new(f);
-        while segment_index * 5000000 < file_size {
-            let read_size: usize = if (segment_index + 1) * 5000000 < file_size {
-                5000000
-            } else {
-                (file_size - segment_index * 5000000) as usize
-            };
-            let mut cursor = Cursor::new(vec![0; read_size]);
-            reader.read(cursor.get_mut())?;
-            let form = Form::new()
-                .text("command", "APPEND")
-                .text("media_id", media_id.clone())
-                .text("segment_index", segment_index.to_string())
-                .part("media", Part::bytes(cursor.into_inner()));
-
-            let response = self
-                .multipart(
-                    "https://upload.twitter.com/1.1/media/upload.json",
-                    &vec![],
-                    form,
-                )
-                .await?;
-            segment_index = segment_index + 1;
-            let result = TwapiResponse::new(response).await;
-            if !result.is_success() {
-                return Ok(result);
-            }
-        }
+    /// Chunked upload by MediaUploader, non-2xx response is returned as Ok
+    async fn post_media_upload_chunk_bytes(
+        &self,
+        data: &[u8],
+        media_type: &str,
+        media_category: &str,
+        additional_owners: Option<String>,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let uploader = chunk_uploader(media_type, media_category, additional_owners);
+        api_error_as_response(uploader.upload_bytes(self, data).await)
+    }
 
-        let form = Form::new()
-            .text("command", "FINALIZE")
-            .text("media_id", media_id.clone());
+    async fn post_media_metadata_create(
+        &self,
+        value: &serde_json::Value,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .json(
+                &format!("{}/1.1/media/metadata/create.json", self.upload_base_url()),
+                &vec![],
+                value,
+            )
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
 
-        let response = self
-            .multipart(
-                "https://upload.twitter.com/1.1/media/upload.json",
+    async fn post_media_subtitles_create(
+        &self,
+        value: &serde_json::Value,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .json(
+                &format!("{}/1.1/media/subtitles/create.json", self.upload_base_url()),
                 &vec![],
-                form,
+                value,
             )
             .await?;
-        let result = TwapiResponse::new(response).await;
-        if !result.is_success() {
-            return Ok(result);
-        }
-        let json = result.copy_json_value().unwrap();
-        let processing_info = json.get("processing_info");
-        if processing_info == None {
-            Ok(result)
-        } else {
-            // check only processing_info included.
-            // if not included call then you get "Invalid mediaId.".
-            self.get_media_upload_until_succeeded(&media_id).await
-        }
+        Ok(TwapiResponse::new(res).await)
     }
 
-    async fn post_media_metadata_create(
+    async fn post_media_subtitles_delete(
         &self,
         value: &serde_json::Value,
     ) -> Result<TwapiResponse, TwapiError> {
         let res = self
             .json(
-                "https://upload.twitter.com/1.1/media/metadata/create.json",
+                &format!("{}/1.1/media/subtitles/delete.json", self.upload_base_url()),
                 &vec![],
                 value,
             )
@@ -425,7 +832,7 @@ pub trait Twapi {
     ) -> Result<TwapiResponse, TwapiError> {
         let res = self
             .post(
-                &make_account_activity_uri("webhooks", env_name, None),
+                &make_account_activity_uri(self.api_base_url(), "webhooks", env_name, None),
                 &vec![("url", uri)],
                 &vec![],
             )
@@ -439,7 +846,7 @@ pub trait Twapi {
     ) -> Result<TwapiResponse, TwapiError> {
         let res = self
             .get(
-                &make_account_activity_uri("webhooks", env_name, None),
+                &make_account_activity_uri(self.api_base_url(), "webhooks", env_name, None),
                 &vec![],
             )
             .await?;
@@ -453,7 +860,27 @@ pub trait Twapi {
     ) -> Result<TwapiResponse, TwapiError> {
         let res = self
             .put(
-                &make_account_activity_uri("webhooks", env_name, None),
+                &make_account_activity_uri(self.api_base_url(), "webhooks", env_name, None),
+                &vec![],
+            )
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
+
+    /// Trigger CRC of the webhook, valid becomes true if it succeeds
+    async fn put_account_activity_webhooks_id(
+        &self,
+        webhook_id: &str,
+        env_name: Option<&str>,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .put(
+                &make_account_activity_uri(
+                    self.api_base_url(),
+                    "webhooks",
+                    env_name,
+                    Some(webhook_id),
+                ),
                 &vec![],
             )
             .await?;
@@ -467,7 +894,12 @@ pub trait Twapi {
     ) -> Result<TwapiResponse, TwapiError> {
         let res = self
             .delete(
-                &make_account_activity_uri("webhooks", env_name, Some(webhook_id)),
+                &make_account_activity_uri(
+                    self.api_base_url(),
+                    "webhooks",
+                    env_name,
+                    Some(webhook_id),
+                ),
                 &vec![],
             )
             .await?;
@@ -480,7 +912,7 @@ pub trait Twapi {
     ) -> Result<TwapiResponse, TwapiError> {
         let res = self
             .post(
-                &make_account_activity_uri("subscriptions", env_name, None),
+                &make_account_activity_uri(self.api_base_url(), "subscriptions", env_name, None),
                 &vec![],
                 &vec![],
             )
@@ -491,7 +923,10 @@ pub trait Twapi {
     async fn get_account_activity_all_count(&self) -> Result<TwapiResponse, TwapiError> {
         let res = self
             .get(
-                "https://api.twitter.com/1.1/account_activity/all/count.json",
+                &format!(
+                    "{}/1.1/account_activity/all/count.json",
+                    self.api_base_url()
+                ),
                 &vec![],
             )
             .await?;
@@ -504,7 +939,7 @@ pub trait Twapi {
     ) -> Result<TwapiResponse, TwapiError> {
         let res = self
             .get(
-                &make_account_activity_uri("subscriptions", env_name, None),
+                &make_account_activity_uri(self.api_base_url(), "subscriptions", env_name, None),
                 &vec![],
             )
             .await?;
@@ -517,7 +952,12 @@ pub trait Twapi {
     ) -> Result<TwapiResponse, TwapiError> {
         let res = self
             .get(
-                &make_account_activity_uri("subscriptions", env_name, Some("list")),
+                &make_account_activity_uri(
+                    self.api_base_url(),
+                    "subscriptions",
+                    env_name,
+                    Some("list"),
+                ),
                 &vec![],
             )
             .await?;
@@ -530,7 +970,27 @@ pub trait Twapi {
     ) -> Result<TwapiResponse, TwapiError> {
         let res = self
             .delete(
-                &make_account_activity_uri("subscriptions", env_name, None),
+                &make_account_activity_uri(self.api_base_url(), "subscriptions", env_name, None),
+                &vec![],
+            )
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
+
+    /// Deactivate subscription of the user, by ApplicationAuth
+    async fn delete_account_activity_subscriptions_user_id(
+        &self,
+        user_id: &str,
+        env_name: &str,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .delete(
+                &make_account_activity_uri(
+                    self.api_base_url(),
+                    "subscriptions",
+                    Some(env_name),
+                    Some(user_id),
+                ),
                 &vec![],
             )
             .await?;
@@ -538,17 +998,88 @@ pub trait Twapi {
     }
 }
 
+fn chunk_uploader(
+    media_type: &str,
+    media_category: &str,
+    additional_owners: Option<String>,
+) -> media::MediaUploader {
+    let uploader = media::MediaUploader::new(media_type, media_category);
+    match additional_owners {
+        Some(additional_owners) => uploader.additional_owners(&additional_owners),
+        None => uploader,
+    }
+}
+
+// MediaUploader returns non-2xx as TwapiError::Api, post_media_upload_chunk* return it as Ok
+fn api_error_as_response(
+    result: Result<TwapiResponse, TwapiError>,
+) -> Result<TwapiResponse, TwapiError> {
+    match result {
+        Err(TwapiError::Api(err)) => Ok(TwapiResponse {
+            status_code: err.status_code,
+            json: err.json,
+            headers: HeaderMap::new(),
+            rate_limit: None,
+        }),
+        result => result,
+    }
+}
+
+// Twitter style error response of a transport method the Twapi does not support
+fn not_implemented_response(method: &str) -> Response {
+    let body = serde_json::json!({
+        "errors": [{ "message": format!("{} is not implemented", method) }]
+    });
+    let mut response = http::Response::new(body.to_string());
+    *response.status_mut() = http::StatusCode::NOT_IMPLEMENTED;
+    Response::from(response)
+}
+
+// twapi_reqwest has no PUT with body and keeps its transport(raw) private,
+// so this is the same request as its raw::put plus the JSON body.
+// twapi_reqwest also builds a Client per request.
+async fn send_put_json(
+    uri: &str,
+    query_options: &[(&str, &str)],
+    json: &serde_json::Value,
+    authorization: &str,
+) -> TResult {
+    twapi_reqwest::reqwest::Client::new()
+        .put(uri)
+        .header("Authorization", authorization)
+        .query(query_options)
+        .json(json)
+        .send()
+        .await
+}
+
 /// Application Only Authenticaiton by oauth2
 pub struct ApplicationAuth {
     bearer_token: String,
+    api_base_url: String,
+    upload_base_url: String,
 }
 
 impl ApplicationAuth {
     pub fn new(bearer_token: &str) -> ApplicationAuth {
         ApplicationAuth {
             bearer_token: String::from(bearer_token),
+            api_base_url: String::from(API_BASE_URL),
+            upload_base_url: String::from(UPLOAD_BASE_URL),
         }
     }
+
+    /// Replace https://api.twitter.com, e.g. with a local mock server
+    pub fn with_api_base_url(mut self, api_base_url: &str) -> ApplicationAuth {
+        self.api_base_url = trim_base_url(api_base_url);
+        self
+    }
+
+    /// Replace https://upload.twitter.com, e.g. with a local mock server
+    pub fn with_upload_base_url(mut self, upload_base_url: &str) -> ApplicationAuth {
+        self.upload_base_url = trim_base_url(upload_base_url);
+        self
+    }
 }
 
 #[async_trait]
@@ -586,6 +1117,24 @@ impl Twapi for ApplicationAuth {
     ) -> TResult {
         twapi_reqwest::v2::json(uri, query_options, json, &self.bearer_token).await
     }
+
+    async fn put_json(
+        &self,
+        uri: &str,
+        query_options: &Vec<(&str, &str)>,
+        json: &serde_json::Value,
+    ) -> TResult {
+        let authorization = twapi_oauth::oauth2_authorization_header(&self.bearer_token);
+        send_put_json(uri, query_options, json, &authorization).await
+    }
+
+    fn api_base_url(&self) -> &str {
+        &self.api_base_url
+    }
+
+    fn upload_base_url(&self) -> &str {
+        &self.upload_base_url
+    }
 }
 
 /// User Authenticaiton by oauth1
@@ -594,6 +1143,8 @@ pub struct UserAuth {
     consumer_secret: String,
     access_token: String,
     access_token_secret: String,
+    api_base_url: String,
+    upload_base_url: String,
 }
 
 impl UserAuth {
@@ -608,8 +1159,22 @@ impl UserAuth {
             consumer_secret: String::from(consumer_secret),
             access_token: String::from(access_token),
             access_token_secret: String::from(access_token_secret),
+            api_base_url: String::from(API_BASE_URL),
+            upload_base_url: String::from(UPLOAD_BASE_URL),
         }
     }
+
+    /// Replace https://api.twitter.com, e.g. with a local mock server
+    pub fn with_api_base_url(mut self, api_base_url: &str) -> UserAuth {
+        self.api_base_url = trim_base_url(api_base_url);
+        self
+    }
+
+    /// Replace https://upload.twitter.com, e.g. with a local mock server
+    pub fn with_upload_base_url(mut self, upload_base_url: &str) -> UserAuth {
+        self.upload_base_url = trim_base_url(upload_base_url);
+        self
+    }
 }
 
 #[async_trait]
@@ -698,4 +1263,277 @@ impl Twapi for UserAuth {
         )
         .await
     }
+
+    async fn put_json(
+        &self,
+        uri: &str,
+        query_options: &Vec<(&str, &str)>,
+        json: &serde_json::Value,
+    ) -> TResult {
+        let authorization = twapi_oauth::oauth1_authorization_header(
+            &self.consumer_key,
+            &self.consumer_secret,
+            &self.access_token,
+            &self.access_token_secret,
+            "PUT",
+            uri,
+            query_options,
+        );
+        send_put_json(uri, query_options, json, &authorization).await
+    }
+
+    fn api_base_url(&self) -> &str {
+        &self.api_base_url
+    }
+
+    fn upload_base_url(&self) -> &str {
+        &self.upload_base_url
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockResponse, MockServer, MockTwapi};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn put_json_defaults_to_not_implemented() {
+        let twapi = MockTwapi::new();
+        let result = twapi
+            .put_direct_messages_welcome_messages_update("1", &serde_json::json!({}))
+            .await
+            .unwrap();
+        assert_eq!(result.status_code, 501);
+        let err = result.into_result().unwrap_err();
+        match err {
+            TwapiError::Api(err) => {
+                assert_eq!(err.errors[0].message, "put_json is not implemented")
+            }
+            err => panic!("unexpected {:?}", err),
+        }
+        assert!(twapi.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn upload_chunk_bytes_uses_media_uploader() {
+        let twapi = MockTwapi::new();
+        twapi
+            .push(MockResponse::new(200, json!({"media_id_string": "1"})))
+            .push(MockResponse::new(204, json!(null)))
+            .push(MockResponse::new(200, json!({"media_id_string": "1"})));
+        let result = twapi
+            .post_media_upload_chunk_bytes(&[0; 10], "video/mp4", "tweet_video", None)
+            .await
+            .unwrap();
+        assert_eq!(result.status_code, 200);
+        let requests = twapi.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests
+            .iter()
+            .all(|it| it.uri == "https://upload.twitter.com/1.1/media/upload.json"));
+    }
+
+    #[tokio::test]
+    async fn upload_chunk_bytes_returns_error_response_as_ok() {
+        let twapi = MockTwapi::new();
+        twapi
+            .push(MockResponse::new(200, json!({"media_id_string": "1"})))
+            .push(MockResponse::new(
+                400,
+                json!({"errors": [{"code": 324, "message": "bad"}]}),
+            ));
+        let result = twapi
+            .post_media_upload_chunk_bytes(&[0; 10], "video/mp4", "tweet_video", None)
+            .await
+            .unwrap();
+        assert_eq!(result.status_code, 400);
+        assert_eq!(result.error().unwrap().errors[0].code, Some(324));
+        assert_eq!(twapi.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn upload_chunk_bytes_validates_size() {
+        let twapi = MockTwapi::new();
+        let data = vec![0; 5 * 1024 * 1024 + 1];
+        let result = twapi
+            .post_media_upload_chunk_bytes(&data, "image/png", "tweet_image", None)
+            .await;
+        assert!(matches!(
+            result,
+            Err(TwapiError::Media(media::MediaError::TooLarge { .. }))
+        ));
+        assert!(twapi.requests().is_empty());
+    }
+
+    fn api_error(status_code: u16, json: serde_json::Value) -> ApiError {
+        ApiError::new(status_code, Some(&json))
+    }
+
+    #[test]
+    fn parses_v1_errors() {
+        let err = api_error(
+            403,
+            json!({"errors": [
+                {"code": 187, "message": "Status is a duplicate."},
+                {"code": 88, "message": "Rate limit exceeded"}
+            ]}),
+        );
+        assert_eq!(
+            err.errors,
+            vec![
+                ApiErrorDetail {
+                    code: Some(187),
+                    message: String::from("Status is a duplicate.")
+                },
+                ApiErrorDetail {
+                    code: Some(88),
+                    message: String::from("Rate limit exceeded")
+                },
+            ]
+        );
+        assert_eq!(
+            err.kinds(),
+            vec![ApiErrorKind::DuplicateStatus, ApiErrorKind::RateLimited]
+        );
+        assert!(err.has(ApiErrorKind::DuplicateStatus));
+        assert!(err.is_rate_limited());
+
+        let err = api_error(401, json!({"error": "Not authorized."}));
+        assert_eq!(err.errors[0].message, "Not authorized.");
+        assert_eq!(err.errors[0].kind(), None);
+        assert!(err.kinds().is_empty());
+    }
+
+    #[test]
+    fn parses_v2_errors() {
+        let err = api_error(
+            401,
+            json!({
+                "title": "Unauthorized",
+                "type": "about:blank",
+                "status": 401,
+                "detail": "Unauthorized"
+            }),
+        );
+        assert_eq!(
+            err.errors,
+            vec![ApiErrorDetail {
+                code: None,
+                message: String::from("Unauthorized")
+            }]
+        );
+        let err = api_error(
+            400,
+            json!({"title": "Invalid Request", "type": "https://api.twitter.com/2/problems/invalid-request"}),
+        );
+        assert_eq!(err.errors[0].message, "Invalid Request");
+        let err = api_error(
+            400,
+            json!({"errors": [{"parameters": {"ids": ["x"]}, "message": "The `ids` query parameter value [x] is not valid"}]}),
+        );
+        assert_eq!(err.errors[0].code, None);
+        assert_eq!(
+            err.errors[0].message,
+            "The `ids` query parameter value [x] is not valid"
+        );
+        let err = api_error(
+            200,
+            json!({"errors": [{"title": "Not Found Error", "detail": "Could not find tweet with ids: [1]."}]}),
+        );
+        assert_eq!(err.errors[0].message, "Could not find tweet with ids: [1].");
+    }
+
+    #[test]
+    fn maps_error_codes() {
+        assert_eq!(ApiErrorKind::from(32), ApiErrorKind::AuthenticationFailed);
+        assert_eq!(ApiErrorKind::from(144), ApiErrorKind::StatusNotFound);
+        assert_eq!(ApiErrorKind::from(349), ApiErrorKind::CannotSendMessage);
+        assert_eq!(ApiErrorKind::from(999), ApiErrorKind::Other(999));
+        let err = api_error(400, json!({"errors": [{"code": 999, "message": "new"}]}));
+        assert_eq!(err.kinds(), vec![ApiErrorKind::Other(999)]);
+        assert!(!err.is_rate_limited());
+        assert!(api_error(429, json!({})).is_rate_limited());
+    }
+
+    #[tokio::test]
+    async fn non_json_error_body() {
+        let twapi = MockTwapi::new();
+        twapi.push(MockResponse {
+            status_code: 503,
+            headers: vec![],
+            body: String::from("<html>Over capacity</html>"),
+        });
+        let result = twapi.get_verify_credentials(&vec![]).await.unwrap();
+        assert!(result.json.is_none());
+        match result.into_result() {
+            Err(TwapiError::Api(err)) => {
+                assert_eq!(err.status_code, 503);
+                assert!(err.errors.is_empty());
+                assert!(err.json.is_none());
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+        let twapi = MockTwapi::new();
+        twapi.push(MockResponse {
+            status_code: 200,
+            headers: vec![],
+            body: String::from("not json"),
+        });
+        let result = twapi.get_verify_credentials(&vec![]).await.unwrap();
+        match result.parse::<serde_json::Value>() {
+            Err(TwapiError::NotExists) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn trims_trailing_slash_of_base_urls() {
+        let app = ApplicationAuth::new("bearer")
+            .with_api_base_url("http://localhost:8080//")
+            .with_upload_base_url("http://localhost:8081/");
+        assert_eq!(app.api_base_url(), "http://localhost:8080");
+        assert_eq!(app.upload_base_url(), "http://localhost:8081");
+        let user = UserAuth::new("ck", "cs", "at", "as").with_api_base_url("http://localhost/");
+        assert_eq!(user.api_base_url(), "http://localhost");
+        assert_eq!(user.upload_base_url(), UPLOAD_BASE_URL);
+        assert_eq!(ApplicationAuth::new("bearer").api_base_url(), API_BASE_URL);
+    }
+
+    #[tokio::test]
+    async fn endpoints_use_api_base_url() {
+        let server = MockServer::start(vec![MockResponse::new(200, json!({}))]);
+        let app =
+            ApplicationAuth::new("bearer").with_api_base_url(&format!("{}/", server.base_url()));
+        let result = app.get_search_tweets(&vec![("q", "rust")]).await.unwrap();
+        assert_eq!(result.status_code, 200);
+        assert_eq!(
+            server.requests()[0].request_line,
+            "GET /1.1/search/tweets.json?q=rust HTTP/1.1"
+        );
+    }
+
+    #[tokio::test]
+    async fn endpoints_use_upload_base_url() {
+        let server = MockServer::start(vec![MockResponse::new(200, json!({}))]);
+        let user = UserAuth::new("ck", "cs", "at", "as").with_upload_base_url(server.base_url());
+        let result = user.get_media_upload("10").await.unwrap();
+        assert_eq!(result.status_code, 200);
+        assert_eq!(
+            server.requests()[0].request_line,
+            "GET /1.1/media/upload.json?command=STATUS&media_id=10 HTTP/1.1"
+        );
+    }
+
+    #[test]
+    fn authorize_uri_uses_https_base_url() {
+        assert_eq!(
+            oauth1::authorize_uri(API_BASE_URL, "token"),
+            "https://api.twitter.com/oauth/authorize?oauth_token=token"
+        );
+        assert_eq!(
+            oauth1::authorize_uri("http://localhost:8080/", "token"),
+            "http://localhost:8080/oauth/authorize?oauth_token=token"
+        );
+    }
 }
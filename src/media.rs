@@ -0,0 +1,978 @@
+//! Chunked media upload
+use super::{retry::RetryPolicy, Twapi, TwapiError, TwapiResponse};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::SeekFrom,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+use twapi_reqwest::reqwest::multipart::{Form, Part};
+
+/// Maximum size of one APPEND segment
+pub const MAX_CHUNK_SIZE: usize = 5 * 1024 * 1024;
+
+/// Default size of one APPEND segment
+pub const DEFAULT_CHUNK_SIZE: usize = 5_000_000;
+
+/// Error in media upload
+#[derive(Debug, Clone, PartialEq)]
+pub enum MediaError {
+    /// expires_after_secs of INIT has passed, upload again from the beginning
+    Expired(String),
+    /// Response lacks an expected field
+    InvalidResponse(String),
+    /// Magic bytes match none of MediaKind
+    UnsupportedType,
+    /// Size exceeds the limit of the category
+    TooLarge {
+        category: MediaCategory,
+        size: u64,
+        max_size: u64,
+    },
+    /// processing_info.state is failed, with processing_info.error
+    ProcessingFailed {
+        media_id: String,
+        code: Option<u64>,
+        name: Option<String>,
+        message: Option<String>,
+    },
+    /// Processing did not finish within PollOptions
+    Timeout(String),
+    /// alt_text exceeds the limit of media/metadata/create
+    AltTextTooLong { length: usize, max_length: usize },
+    /// chunk_size or segment_index of UploadCheckpoint can not resume the media
+    InvalidCheckpoint(String),
+}
+
+impl From<MediaError> for TwapiError {
+    fn from(err: MediaError) -> TwapiError {
+        TwapiError::Media(err)
+    }
+}
+
+/// Major brands of ftyp box uploaded as video/mp4
+const MP4_BRANDS: [&[u8; 4]; 12] = [
+    b"isom", b"iso2", b"iso4", b"iso5", b"iso6", b"mp41", b"mp42", b"avc1", b"M4V ", b"M4VP",
+    b"MSNV", b"dash",
+];
+
+/// Media type detected from magic bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaKind {
+    Jpeg,
+    Png,
+    Gif,
+    Webp,
+    Mp4,
+    Mov,
+}
+
+impl MediaKind {
+    /// Bytes needed by detect
+    pub const HEAD_SIZE: usize = 16;
+
+    /// ISO-BMFF other than MP4_BRANDS and QuickTime, such as HEIC, AVIF and M4A, is None
+    pub fn detect(head: &[u8]) -> Option<MediaKind> {
+        if head.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            Some(MediaKind::Jpeg)
+        } else if head.starts_with(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]) {
+            Some(MediaKind::Png)
+        } else if head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a") {
+            Some(MediaKind::Gif)
+        } else if head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WEBP") {
+            Some(MediaKind::Webp)
+        } else if head.get(4..8) == Some(b"ftyp") {
+            // major brand, HEIC, AVIF and M4A are also ftyp
+            match head.get(8..12)? {
+                b"qt  " => Some(MediaKind::Mov),
+                brand if MP4_BRANDS.iter().any(|it| &it[..] == brand) => Some(MediaKind::Mp4),
+                _ => None,
+            }
+        } else {
+            match head.get(4..8) {
+                Some(b"moov") | Some(b"mdat") | Some(b"wide") | Some(b"free") => {
+                    Some(MediaKind::Mov)
+                }
+                _ => None,
+            }
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            MediaKind::Jpeg => "image/jpeg",
+            MediaKind::Png => "image/png",
+            MediaKind::Gif => "image/gif",
+            MediaKind::Webp => "image/webp",
+            MediaKind::Mp4 => "video/mp4",
+            MediaKind::Mov => "video/quicktime",
+        }
+    }
+}
+
+/// Where uploaded media is used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaUsage {
+    Tweet,
+    DirectMessage,
+}
+
+/// media_category of INIT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaCategory {
+    TweetImage,
+    TweetGif,
+    TweetVideo,
+    DmImage,
+    DmGif,
+    DmVideo,
+}
+
+impl MediaCategory {
+    pub fn new(kind: MediaKind, usage: MediaUsage) -> MediaCategory {
+        match (kind, usage) {
+            (MediaKind::Gif, MediaUsage::Tweet) => MediaCategory::TweetGif,
+            (MediaKind::Mp4, MediaUsage::Tweet) | (MediaKind::Mov, MediaUsage::Tweet) => {
+                MediaCategory::TweetVideo
+            }
+            (_, MediaUsage::Tweet) => MediaCategory::TweetImage,
+            (MediaKind::Gif, MediaUsage::DirectMessage) => MediaCategory::DmGif,
+            (MediaKind::Mp4, MediaUsage::DirectMessage)
+            | (MediaKind::Mov, MediaUsage::DirectMessage) => MediaCategory::DmVideo,
+            (_, MediaUsage::DirectMessage) => MediaCategory::DmImage,
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<MediaCategory> {
+        match value {
+            "tweet_image" => Some(MediaCategory::TweetImage),
+            "tweet_gif" => Some(MediaCategory::TweetGif),
+            "tweet_video" => Some(MediaCategory::TweetVideo),
+            "dm_image" => Some(MediaCategory::DmImage),
+            "dm_gif" => Some(MediaCategory::DmGif),
+            "dm_video" => Some(MediaCategory::DmVideo),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MediaCategory::TweetImage => "tweet_image",
+            MediaCategory::TweetGif => "tweet_gif",
+            MediaCategory::TweetVideo => "tweet_video",
+            MediaCategory::DmImage => "dm_image",
+            MediaCategory::DmGif => "dm_gif",
+            MediaCategory::DmVideo => "dm_video",
+        }
+    }
+
+    /// Upload size limit in bytes
+    pub fn max_size(&self) -> u64 {
+        match self {
+            MediaCategory::TweetImage | MediaCategory::DmImage => 5 * 1024 * 1024,
+            MediaCategory::TweetGif | MediaCategory::DmGif => 15 * 1024 * 1024,
+            MediaCategory::TweetVideo | MediaCategory::DmVideo => 512 * 1024 * 1024,
+        }
+    }
+
+    pub fn validate(&self, size: u64) -> Result<(), MediaError> {
+        if size > self.max_size() {
+            Err(MediaError::TooLarge {
+                category: *self,
+                size,
+                max_size: self.max_size(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Limits of waiting for media processing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PollOptions {
+    /// Give up when processing takes longer than this
+    pub deadline: Option<Duration>,
+    /// Give up after this number of STATUS requests
+    pub max_polls: Option<u32>,
+}
+
+impl Default for PollOptions {
+    fn default() -> PollOptions {
+        PollOptions {
+            deadline: Some(Duration::from_secs(30 * 60)),
+            max_polls: Some(300),
+        }
+    }
+}
+
+/// Read processing_info of FINALIZE or STATUS response.
+/// None if succeeded, check_after_secs if still processing.
+pub fn check_processing_info(
+    media_id: &str,
+    json: Option<&serde_json::Value>,
+) -> Result<Option<u64>, MediaError> {
+    let invalid = |field: &str| MediaError::InvalidResponse(String::from(field));
+    let processing_info = json
+        .and_then(|it| it.get("processing_info"))
+        .ok_or_else(|| invalid("processing_info"))?;
+    let state = processing_info
+        .get("state")
+        .and_then(|it| it.as_str())
+        .ok_or_else(|| invalid("processing_info.state"))?;
+    match state {
+        "succeeded" => Ok(None),
+        "failed" => {
+            let error = processing_info.get("error");
+            let field = |name: &str| error.and_then(|it| it.get(name));
+            Err(MediaError::ProcessingFailed {
+                media_id: String::from(media_id),
+                code: field("code").and_then(|it| it.as_u64()),
+                name: field("name").and_then(|it| it.as_str()).map(String::from),
+                message: field("message")
+                    .and_then(|it| it.as_str())
+                    .map(String::from),
+            })
+        }
+        _ => processing_info
+            .get("check_after_secs")
+            .and_then(|it| it.as_u64())
+            .map(Some)
+            .ok_or_else(|| invalid("processing_info.check_after_secs")),
+    }
+}
+
+/// media_id_string of INIT, FINALIZE or STATUS response
+pub fn media_id_of(result: &TwapiResponse) -> Result<String, MediaError> {
+    result
+        .json
+        .as_ref()
+        .and_then(|it| it.get("media_id_string"))
+        .and_then(|it| it.as_str())
+        .map(String::from)
+        .ok_or_else(|| MediaError::InvalidResponse(String::from("media_id_string")))
+}
+
+/// Saved state to resume an interrupted upload
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UploadCheckpoint {
+    pub media_id: String,
+    /// Next segment to APPEND
+    pub segment_index: u64,
+    pub chunk_size: usize,
+    /// UTC epoch seconds computed from expires_after_secs
+    pub expires_at: Option<u64>,
+}
+
+impl UploadCheckpoint {
+    pub fn is_expired(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => now_secs() >= expires_at,
+            None => false,
+        }
+    }
+
+    /// chunk_size is 1..=MAX_CHUNK_SIZE and segment_index is within total_bytes
+    pub fn validate(&self, total_bytes: u64) -> Result<(), MediaError> {
+        if self.chunk_size == 0 || self.chunk_size > MAX_CHUNK_SIZE {
+            return Err(MediaError::InvalidCheckpoint(format!(
+                "chunk_size {} is not in 1..={}",
+                self.chunk_size, MAX_CHUNK_SIZE
+            )));
+        }
+        match self.offset() {
+            Some(offset) if offset <= total_bytes => Ok(()),
+            _ => Err(MediaError::InvalidCheckpoint(format!(
+                "segment_index {} of chunk_size {} is beyond total_bytes {}",
+                self.segment_index, self.chunk_size, total_bytes
+            ))),
+        }
+    }
+
+    // Bytes already uploaded
+    fn offset(&self) -> Option<u64> {
+        self.segment_index.checked_mul(self.chunk_size as u64)
+    }
+}
+
+/// Reported after INIT and every APPEND
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadProgress {
+    pub uploaded_bytes: u64,
+    pub total_bytes: u64,
+    pub checkpoint: UploadCheckpoint,
+}
+
+type ProgressCallback = Arc<dyn Fn(&UploadProgress) + Send + Sync>;
+
+/// INIT, APPEND and FINALIZE with progress, retry and resume
+#[derive(Clone)]
+pub struct MediaUploader {
+    media_type: Option<String>,
+    media_category: Option<String>,
+    usage: MediaUsage,
+    additional_owners: Option<String>,
+    chunk_size: usize,
+    retry_policy: RetryPolicy,
+    poll_options: PollOptions,
+    on_progress: Option<ProgressCallback>,
+}
+
+impl MediaUploader {
+    pub fn new(media_type: &str, media_category: &str) -> MediaUploader {
+        MediaUploader {
+            media_type: Some(String::from(media_type)),
+            media_category: Some(String::from(media_category)),
+            ..MediaUploader::auto(MediaUsage::Tweet)
+        }
+    }
+
+    /// Detect media_type and media_category from magic bytes
+    pub fn auto(usage: MediaUsage) -> MediaUploader {
+        MediaUploader {
+            media_type: None,
+            media_category: None,
+            usage,
+            additional_owners: None,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            retry_policy: RetryPolicy::default(),
+            poll_options: PollOptions::default(),
+            on_progress: None,
+        }
+    }
+
+    pub fn media_type(mut self, media_type: &str) -> MediaUploader {
+        self.media_type = Some(String::from(media_type));
+        self
+    }
+
+    pub fn media_category(mut self, media_category: &str) -> MediaUploader {
+        self.media_category = Some(String::from(media_category));
+        self
+    }
+
+    pub fn additional_owners(mut self, additional_owners: &str) -> MediaUploader {
+        self.additional_owners = Some(String::from(additional_owners));
+        self
+    }
+
+    /// Clamped to 1..=MAX_CHUNK_SIZE
+    pub fn chunk_size(mut self, chunk_size: usize) -> MediaUploader {
+        self.chunk_size = chunk_size.clamp(1, MAX_CHUNK_SIZE);
+        self
+    }
+
+    /// Retry of each APPEND on connection error, 429 and 5xx
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> MediaUploader {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Limits of waiting for processing after FINALIZE
+    pub fn poll_options(mut self, poll_options: PollOptions) -> MediaUploader {
+        self.poll_options = poll_options;
+        self
+    }
+
+    pub fn on_progress<F>(mut self, on_progress: F) -> MediaUploader
+    where
+        F: Fn(&UploadProgress) + Send + Sync + 'static,
+    {
+        self.on_progress = Some(Arc::new(on_progress));
+        self
+    }
+
+    /// Send progress to a channel instead of callback
+    pub fn progress_sender(
+        self,
+        sender: tokio::sync::mpsc::UnboundedSender<UploadProgress>,
+    ) -> MediaUploader {
+        self.on_progress(move |progress| {
+            let _ = sender.send(progress.clone());
+        })
+    }
+
+    pub async fn upload<T: Twapi + Sync + ?Sized>(
+        &self,
+        twapi: &T,
+        file: &str,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let mut reader = tokio::fs::File::open(file).await?;
+        let total_bytes = reader.metadata().await?.len();
+        let head = read_head(&mut reader).await?;
+        reader.seek(SeekFrom::Start(0)).await?;
+        self.upload_from(twapi, &mut reader, total_bytes, &head, None)
+            .await
+    }
+
+    /// Continue from checkpoint reported by on_progress
+    pub async fn resume<T: Twapi + Sync + ?Sized>(
+        &self,
+        twapi: &T,
+        file: &str,
+        checkpoint: UploadCheckpoint,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let mut reader = tokio::fs::File::open(file).await?;
+        let total_bytes = reader.metadata().await?.len();
+        checkpoint.validate(total_bytes)?;
+        reader
+            .seek(SeekFrom::Start(
+                checkpoint.segment_index * checkpoint.chunk_size as u64,
+            ))
+            .await?;
+        self.upload_from(twapi, &mut reader, total_bytes, &[], Some(checkpoint))
+            .await
+    }
+
+    pub async fn upload_bytes<T: Twapi + Sync + ?Sized>(
+        &self,
+        twapi: &T,
+        data: &[u8],
+    ) -> Result<TwapiResponse, TwapiError> {
+        let head = &data[..data.len().min(MediaKind::HEAD_SIZE)];
+        self.upload_from(
+            twapi,
+            &mut std::io::Cursor::new(data),
+            data.len() as u64,
+            head,
+            None,
+        )
+        .await
+    }
+
+    pub async fn resume_bytes<T: Twapi + Sync + ?Sized>(
+        &self,
+        twapi: &T,
+        data: &[u8],
+        checkpoint: UploadCheckpoint,
+    ) -> Result<TwapiResponse, TwapiError> {
+        checkpoint.validate(data.len() as u64)?;
+        let mut reader = std::io::Cursor::new(data);
+        reader.set_position(checkpoint.segment_index * checkpoint.chunk_size as u64);
+        self.upload_from(twapi, &mut reader, data.len() as u64, &[], Some(checkpoint))
+            .await
+    }
+
+    /// total_bytes must be the exact length of reader
+    pub async fn upload_reader<T, R>(
+        &self,
+        twapi: &T,
+        mut reader: R,
+        total_bytes: u64,
+    ) -> Result<TwapiResponse, TwapiError>
+    where
+        T: Twapi + Sync + ?Sized,
+        R: AsyncRead + Unpin + Send,
+    {
+        let head = read_head(&mut reader).await?;
+        let mut reader = std::io::Cursor::new(head.clone()).chain(reader);
+        self.upload_from(twapi, &mut reader, total_bytes, &head, None)
+            .await
+    }
+
+    /// reader must start from the beginning, uploaded segments are skipped
+    pub async fn resume_reader<T, R>(
+        &self,
+        twapi: &T,
+        reader: R,
+        total_bytes: u64,
+        checkpoint: UploadCheckpoint,
+    ) -> Result<TwapiResponse, TwapiError>
+    where
+        T: Twapi + Sync + ?Sized,
+        R: AsyncRead + Unpin + Send,
+    {
+        checkpoint.validate(total_bytes)?;
+        let skip = checkpoint.segment_index * checkpoint.chunk_size as u64;
+        let mut reader = reader.take(skip);
+        tokio::io::copy(&mut reader, &mut tokio::io::sink()).await?;
+        let mut reader = reader.into_inner();
+        self.upload_from(twapi, &mut reader, total_bytes, &[], Some(checkpoint))
+            .await
+    }
+
+    /// media_type and media_category, detected from head if not given
+    pub fn resolve(&self, head: &[u8], total_bytes: u64) -> Result<(String, String), MediaError> {
+        let kind = MediaKind::detect(head);
+        let media_type = match (&self.media_type, kind) {
+            (Some(media_type), _) => media_type.clone(),
+            (None, Some(kind)) => String::from(kind.mime_type()),
+            (None, None) => return Err(MediaError::UnsupportedType),
+        };
+        let media_category = match (&self.media_category, kind) {
+            (Some(media_category), _) => media_category.clone(),
+            (None, Some(kind)) => String::from(MediaCategory::new(kind, self.usage).as_str()),
+            (None, None) => return Err(MediaError::UnsupportedType),
+        };
+        if let Some(category) = MediaCategory::parse(&media_category) {
+            category.validate(total_bytes)?;
+        }
+        Ok((media_type, media_category))
+    }
+
+    // reader must be positioned at checkpoint.segment_index
+    async fn upload_from<T, R>(
+        &self,
+        twapi: &T,
+        reader: &mut R,
+        total_bytes: u64,
+        head: &[u8],
+        checkpoint: Option<UploadCheckpoint>,
+    ) -> Result<TwapiResponse, TwapiError>
+    where
+        T: Twapi + Sync + ?Sized,
+        R: AsyncRead + Unpin + Send,
+    {
+        let uri = format!("{}/1.1/media/upload.json", twapi.upload_base_url());
+        let mut checkpoint = match checkpoint {
+            Some(checkpoint) if checkpoint.is_expired() => {
+                return Err(MediaError::Expired(checkpoint.media_id).into());
+            }
+            Some(checkpoint) => checkpoint,
+            None => {
+                let (media_type, media_category) = self.resolve(head, total_bytes)?;
+                self.init(twapi, &uri, total_bytes, media_type, media_category)
+                    .await?
+            }
+        };
+        let chunk_size = checkpoint.chunk_size as u64;
+        let mut uploaded_bytes = total_bytes.min(checkpoint.segment_index * chunk_size);
+        self.report(uploaded_bytes, total_bytes, &checkpoint);
+
+        while uploaded_bytes < total_bytes {
+            let read_size = chunk_size.min(total_bytes - uploaded_bytes);
+            let mut chunk = vec![0; read_size as usize];
+            reader.read_exact(&mut chunk).await?;
+            self.append(twapi, &uri, &checkpoint, chunk).await?;
+            checkpoint.segment_index += 1;
+            uploaded_bytes += read_size;
+            self.report(uploaded_bytes, total_bytes, &checkpoint);
+        }
+
+        self.finalize(twapi, &uri, &checkpoint.media_id).await
+    }
+
+    async fn init<T: Twapi + Sync + ?Sized>(
+        &self,
+        twapi: &T,
+        uri: &str,
+        total_bytes: u64,
+        media_type: String,
+        media_category: String,
+    ) -> Result<UploadCheckpoint, TwapiError> {
+        let form = Form::new()
+            .text("command", "INIT")
+            .text("total_bytes", total_bytes.to_string())
+            .text("media_type", media_type)
+            .text("media_category", media_category);
+        let form = match self.additional_owners {
+            Some(ref additional_owners) => {
+                form.text("additional_owners", additional_owners.clone())
+            }
+            None => form,
+        };
+        let response = twapi.multipart(uri, &vec![], form).await?;
+        let json = TwapiResponse::new(response)
+            .await
+            .into_result()?
+            .json
+            .unwrap_or_default();
+        let media_id = json
+            .get("media_id_string")
+            .and_then(|it| it.as_str())
+            .ok_or_else(|| MediaError::InvalidResponse(String::from("media_id_string")))?;
+        let expires_at = json
+            .get("expires_after_secs")
+            .and_then(|it| it.as_u64())
+            .map(|it| now_secs() + it);
+        Ok(UploadCheckpoint {
+            media_id: String::from(media_id),
+            segment_index: 0,
+            chunk_size: self.chunk_size,
+            expires_at,
+        })
+    }
+
+    async fn append<T: Twapi + Sync + ?Sized>(
+        &self,
+        twapi: &T,
+        uri: &str,
+        checkpoint: &UploadCheckpoint,
+        chunk: Vec<u8>,
+    ) -> Result<(), TwapiError> {
+        let mut attempt = 0;
+        loop {
+            // Form can not be cloned, so build it on every attempt
+            let form = Form::new()
+                .text("command", "APPEND")
+                .text("media_id", checkpoint.media_id.clone())
+                .text("segment_index", checkpoint.segment_index.to_string())
+                .part("media", Part::bytes(chunk.clone()));
+            let result = match twapi.multipart(uri, &vec![], form).await {
+                Ok(response) => TwapiResponse::new(response).await.into_result(),
+                Err(err) => Err(err.into()),
+            };
+            match result {
+                Ok(_) => return Ok(()),
+                Err(err) if attempt < self.retry_policy.max_retries && is_retryable(&err) => {
+                    tokio::time::sleep(self.retry_policy.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    async fn finalize<T: Twapi + Sync + ?Sized>(
+        &self,
+        twapi: &T,
+        uri: &str,
+        media_id: &str,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let form = Form::new()
+            .text("command", "FINALIZE")
+            .text("media_id", String::from(media_id));
+        let response = twapi.multipart(uri, &vec![], form).await?;
+        let result = TwapiResponse::new(response).await.into_result()?;
+        let has_processing_info = match result.json {
+            Some(ref json) => json.get("processing_info").is_some(),
+            None => false,
+        };
+        if has_processing_info {
+            // STATUS is only available when processing_info is included
+            twapi
+                .get_media_upload_until_succeeded_with(media_id, &self.poll_options)
+                .await?
+                .into_result()
+        } else {
+            Ok(result)
+        }
+    }
+
+    fn report(&self, uploaded_bytes: u64, total_bytes: u64, checkpoint: &UploadCheckpoint) {
+        if let Some(ref on_progress) = self.on_progress {
+            on_progress(&UploadProgress {
+                uploaded_bytes,
+                total_bytes,
+                checkpoint: checkpoint.clone(),
+            });
+        }
+    }
+}
+
+async fn read_head<R: AsyncRead + Unpin>(reader: &mut R) -> std::io::Result<Vec<u8>> {
+    let mut head = vec![];
+    reader
+        .take(MediaKind::HEAD_SIZE as u64)
+        .read_to_end(&mut head)
+        .await?;
+    Ok(head)
+}
+
+fn is_retryable(err: &TwapiError) -> bool {
+    match err {
+        TwapiError::Connection(_) => true,
+        TwapiError::Api(err) => err.status_code == 429 || err.status_code >= 500,
+        _ => false,
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mock::{MockResponse, MockServer, MockTwapi},
+        UserAuth,
+    };
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    fn ftyp(brand: &[u8; 4]) -> Vec<u8> {
+        let mut head = vec![0, 0, 0, 0x20];
+        head.extend_from_slice(b"ftyp");
+        head.extend_from_slice(brand);
+        head.extend_from_slice(&[0, 0, 0, 0]);
+        head
+    }
+
+    #[test]
+    fn detects_images() {
+        let detect = MediaKind::detect;
+        assert_eq!(detect(&[0xFF, 0xD8, 0xFF, 0xE0]), Some(MediaKind::Jpeg));
+        assert_eq!(
+            detect(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0]),
+            Some(MediaKind::Png)
+        );
+        assert_eq!(detect(b"GIF87a...."), Some(MediaKind::Gif));
+        assert_eq!(detect(b"GIF89a...."), Some(MediaKind::Gif));
+        assert_eq!(detect(b"RIFF\x10\0\0\0WEBPVP8 "), Some(MediaKind::Webp));
+        assert_eq!(detect(b"RIFF\x10\0\0\0WAVEfmt "), None);
+    }
+
+    #[test]
+    fn detects_videos_by_ftyp_brand() {
+        for brand in MP4_BRANDS.iter() {
+            assert_eq!(MediaKind::detect(&ftyp(brand)), Some(MediaKind::Mp4));
+        }
+        assert_eq!(MediaKind::detect(&ftyp(b"qt  ")), Some(MediaKind::Mov));
+        for brand in [b"heic", b"heix", b"mif1", b"avif", b"M4A ", b"3g2a"].iter() {
+            assert_eq!(MediaKind::detect(&ftyp(brand)), None);
+        }
+        // truncated brand
+        assert_eq!(MediaKind::detect(b"\0\0\0\x20ftypis"), None);
+    }
+
+    #[test]
+    fn detects_mov_without_ftyp() {
+        for atom in [b"moov", b"mdat", b"wide", b"free"].iter() {
+            let mut head = vec![0, 0, 0, 8];
+            head.extend_from_slice(*atom);
+            assert_eq!(MediaKind::detect(&head), Some(MediaKind::Mov));
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_bytes() {
+        assert_eq!(MediaKind::detect(&[]), None);
+        assert_eq!(MediaKind::detect(b"%PDF-1.7"), None);
+        assert_eq!(MediaKind::detect(&[0xFF, 0xD8]), None);
+    }
+
+    #[test]
+    fn resolves_category_from_kind() {
+        let uploader = MediaUploader::auto(MediaUsage::DirectMessage);
+        assert_eq!(
+            uploader.resolve(&ftyp(b"mp42"), 10),
+            Ok((String::from("video/mp4"), String::from("dm_video")))
+        );
+        assert_eq!(
+            uploader.resolve(&ftyp(b"heic"), 10),
+            Err(MediaError::UnsupportedType)
+        );
+    }
+
+    fn checkpoint(segment_index: u64, chunk_size: usize) -> UploadCheckpoint {
+        UploadCheckpoint {
+            media_id: String::from("1"),
+            segment_index,
+            chunk_size,
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn validates_checkpoint() {
+        assert_eq!(checkpoint(0, 1).validate(0), Ok(()));
+        assert_eq!(checkpoint(2, 5).validate(10), Ok(()));
+        assert_eq!(checkpoint(1, MAX_CHUNK_SIZE).validate(u64::MAX), Ok(()));
+        for (segment_index, chunk_size, total_bytes) in [
+            (0, 0, 10),
+            (0, MAX_CHUNK_SIZE + 1, 10),
+            (3, 5, 10),
+            (u64::MAX, 2, u64::MAX),
+        ]
+        .iter()
+        {
+            let result = checkpoint(*segment_index, *chunk_size).validate(*total_bytes);
+            assert!(
+                matches!(result, Err(MediaError::InvalidCheckpoint(_))),
+                "{} {} {}",
+                segment_index,
+                chunk_size,
+                total_bytes
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_checkpoint_before_requests() {
+        let twapi = MockTwapi::new();
+        let uploader = MediaUploader::new("image/png", "tweet_image");
+        let result = uploader
+            .resume_bytes(&twapi, &[0; 10], checkpoint(0, 0))
+            .await;
+        assert!(matches!(
+            result,
+            Err(TwapiError::Media(MediaError::InvalidCheckpoint(_)))
+        ));
+        let result = uploader
+            .resume_reader(&twapi, &[0u8; 10][..], 10, checkpoint(11, 1))
+            .await;
+        assert!(matches!(
+            result,
+            Err(TwapiError::Media(MediaError::InvalidCheckpoint(_)))
+        ));
+        assert!(twapi.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn resumes_remaining_segments() {
+        let twapi = MockTwapi::new();
+        // APPEND, APPEND, FINALIZE
+        twapi
+            .push(MockResponse::new(204, json!(null)))
+            .push(MockResponse::new(204, json!(null)))
+            .push(MockResponse::new(200, json!({"media_id_string": "1"})));
+        let progress = Arc::new(Mutex::new(vec![]));
+        let reported = progress.clone();
+        let result = MediaUploader::new("image/png", "tweet_image")
+            .on_progress(move |it| {
+                reported
+                    .lock()
+                    .unwrap()
+                    .push((it.uploaded_bytes, it.checkpoint.segment_index))
+            })
+            .resume_bytes(&twapi, &[0; 10], checkpoint(1, 4))
+            .await
+            .unwrap();
+        assert_eq!(media_id_of(&result), Ok(String::from("1")));
+        assert_eq!(twapi.requests().len(), 3);
+        assert_eq!(*progress.lock().unwrap(), vec![(4, 1), (8, 2), (10, 3)]);
+    }
+
+    fn png(length: usize) -> Vec<u8> {
+        let mut data = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+        data.extend((0..length - data.len()).map(|it| it as u8));
+        data
+    }
+
+    #[tokio::test]
+    async fn uploads_reader_in_chunks() {
+        let data = png(25);
+        let server = MockServer::start(vec![
+            MockResponse::new(
+                200,
+                json!({"media_id_string": "7", "expires_after_secs": 86400}),
+            ),
+            MockResponse::new(200, json!({})),
+            MockResponse::new(200, json!({})),
+            MockResponse::new(200, json!({})),
+            MockResponse::new(200, json!({"media_id_string": "7", "size": 25})),
+        ]);
+        let twapi = UserAuth::new("ck", "cs", "at", "as").with_upload_base_url(server.base_url());
+        // at most 3 bytes per read, so the head is peeked by several reads
+        let reader = tokio::io::BufReader::with_capacity(3, &data[..]);
+        let result = MediaUploader::auto(MediaUsage::Tweet)
+            .chunk_size(10)
+            .upload_reader(&twapi, reader, data.len() as u64)
+            .await
+            .unwrap();
+        assert_eq!(media_id_of(&result), Ok(String::from("7")));
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 5);
+        for request in requests.iter() {
+            assert_eq!(request.request_line, "POST /1.1/media/upload.json HTTP/1.1");
+        }
+        let init = &requests[0];
+        assert_eq!(init.part("command").as_deref(), Some("INIT"));
+        assert_eq!(init.part("total_bytes").as_deref(), Some("25"));
+        assert_eq!(init.part("media_type").as_deref(), Some("image/png"));
+        assert_eq!(init.part("media_category").as_deref(), Some("tweet_image"));
+        for (segment_index, request) in requests[1..4].iter().enumerate() {
+            let start = segment_index * 10;
+            let end = data.len().min(start + 10);
+            assert_eq!(
+                request.multipart(),
+                vec![
+                    (String::from("command"), b"APPEND".to_vec()),
+                    (String::from("media_id"), b"7".to_vec()),
+                    (
+                        String::from("segment_index"),
+                        segment_index.to_string().into_bytes()
+                    ),
+                    (String::from("media"), data[start..end].to_vec()),
+                ]
+            );
+        }
+        assert_eq!(requests[4].part("command").as_deref(), Some("FINALIZE"));
+        assert_eq!(requests[4].part("media_id").as_deref(), Some("7"));
+    }
+
+    #[tokio::test]
+    async fn rejects_undetected_reader_before_requests() {
+        let twapi = MockTwapi::new();
+        let data = b"%PDF-1.7 not media";
+        let result = MediaUploader::auto(MediaUsage::Tweet)
+            .upload_reader(&twapi, &data[..], data.len() as u64)
+            .await;
+        assert!(matches!(
+            result,
+            Err(TwapiError::Media(MediaError::UnsupportedType))
+        ));
+        assert!(twapi.requests().is_empty());
+    }
+
+    fn retry_uploader(max_retries: u32) -> MediaUploader {
+        MediaUploader::new("image/png", "tweet_image").retry_policy(RetryPolicy {
+            max_retries,
+            jitter: false,
+            ..Default::default()
+        })
+    }
+
+    fn init() -> MockResponse {
+        MockResponse::new(200, json!({"media_id_string": "7"}))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_failed_append() {
+        let twapi = MockTwapi::new();
+        twapi
+            .push(init())
+            .push(MockResponse::new(503, json!({})))
+            .push(MockResponse::new(429, json!({})))
+            .push(MockResponse::new(200, json!({})))
+            .push(MockResponse::new(200, json!({"media_id_string": "7"})));
+        let started = tokio::time::Instant::now();
+        let result = retry_uploader(2)
+            .upload_bytes(&twapi, &png(10))
+            .await
+            .unwrap();
+        assert_eq!(media_id_of(&result), Ok(String::from("7")));
+        // 1s then 2s
+        assert_eq!(started.elapsed().as_secs(), 3);
+        assert_eq!(twapi.requests().len(), 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_append_after_max_retries() {
+        let twapi = MockTwapi::new();
+        twapi
+            .push(init())
+            .push(MockResponse::new(500, json!({})))
+            .push(MockResponse::new(500, json!({})));
+        let result = retry_uploader(1).upload_bytes(&twapi, &png(10)).await;
+        match result {
+            Err(TwapiError::Api(err)) => assert_eq!(err.status_code, 500),
+            result => panic!("unexpected {:?}", result),
+        }
+        // INIT and two APPEND, no FINALIZE
+        assert_eq!(twapi.requests().len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn does_not_retry_append_on_4xx() {
+        let twapi = MockTwapi::new();
+        twapi.push(init()).push(MockResponse::new(
+            400,
+            json!({"errors": [{"code": 324, "message": "Invalid media id."}]}),
+        ));
+        let started = tokio::time::Instant::now();
+        let result = retry_uploader(3).upload_bytes(&twapi, &png(10)).await;
+        match result {
+            Err(TwapiError::Api(err)) => assert_eq!(err.status_code, 400),
+            result => panic!("unexpected {:?}", result),
+        }
+        assert_eq!(started.elapsed().as_secs(), 0);
+        assert_eq!(twapi.requests().len(), 2);
+    }
+}
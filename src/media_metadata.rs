@@ -0,0 +1,311 @@
+//! Alt text, sensitive media warning and subtitles of uploaded media
+use super::{
+    media::{media_id_of, MediaError, MediaUploader},
+    Twapi, TwapiError, TwapiResponse,
+};
+use serde::Serialize;
+
+/// Maximum characters of alt_text
+pub const MAX_ALT_TEXT_LENGTH: usize = 1000;
+
+/// media_type of SRT file
+pub const SUBTITLES_MEDIA_TYPE: &str = "text/srt";
+
+/// media_category of SRT file
+pub const SUBTITLES_MEDIA_CATEGORY: &str = "subtitles";
+
+/// sensitive_media_warning of media/metadata/create
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SensitiveMediaWarning {
+    AdultContent,
+    GraphicViolence,
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct AltText {
+    text: String,
+}
+
+/// Body of media/metadata/create
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MediaMetadata {
+    media_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alt_text: Option<AltText>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    sensitive_media_warning: Vec<SensitiveMediaWarning>,
+}
+
+impl MediaMetadata {
+    pub fn new(media_id: &str) -> MediaMetadata {
+        MediaMetadata {
+            media_id: String::from(media_id),
+            alt_text: None,
+            sensitive_media_warning: vec![],
+        }
+    }
+
+    /// Up to MAX_ALT_TEXT_LENGTH characters
+    pub fn alt_text(mut self, text: &str) -> MediaMetadata {
+        self.alt_text = Some(AltText {
+            text: String::from(text),
+        });
+        self
+    }
+
+    pub fn sensitive_media_warning(mut self, warning: SensitiveMediaWarning) -> MediaMetadata {
+        if !self.sensitive_media_warning.contains(&warning) {
+            self.sensitive_media_warning.push(warning);
+        }
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), MediaError> {
+        if let Some(ref alt_text) = self.alt_text {
+            let length = alt_text.text.chars().count();
+            if length > MAX_ALT_TEXT_LENGTH {
+                return Err(MediaError::AltTextTooLong {
+                    length,
+                    max_length: MAX_ALT_TEXT_LENGTH,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_value(&self) -> Result<serde_json::Value, TwapiError> {
+        self.validate()?;
+        Ok(serde_json::to_value(self)?)
+    }
+
+    /// Validate and call media/metadata/create
+    pub async fn create<T: Twapi + Sync + ?Sized>(
+        &self,
+        twapi: &T,
+    ) -> Result<TwapiResponse, TwapiError> {
+        twapi
+            .post_media_metadata_create(&self.to_value()?)
+            .await?
+            .into_result()
+    }
+}
+
+/// One subtitle track attached to video
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Subtitle {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_id: Option<String>,
+    /// BCP47 code such as EN or JA
+    pub language_code: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct SubtitleInfo {
+    subtitles: Vec<Subtitle>,
+}
+
+/// Body of media/subtitles/create and media/subtitles/delete
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Subtitles {
+    media_id: String,
+    media_category: String,
+    subtitle_info: SubtitleInfo,
+}
+
+impl Subtitles {
+    /// media_id of the video, media_category defaults to TweetVideo
+    pub fn new(media_id: &str) -> Subtitles {
+        Subtitles {
+            media_id: String::from(media_id),
+            media_category: String::from("TweetVideo"),
+            subtitle_info: SubtitleInfo { subtitles: vec![] },
+        }
+    }
+
+    pub fn media_category(mut self, media_category: &str) -> Subtitles {
+        self.media_category = String::from(media_category);
+        self
+    }
+
+    /// Attach uploaded SRT, for create
+    pub fn subtitle(
+        mut self,
+        subtitle_media_id: &str,
+        language_code: &str,
+        display_name: &str,
+    ) -> Subtitles {
+        self.subtitle_info.subtitles.push(Subtitle {
+            media_id: Some(String::from(subtitle_media_id)),
+            language_code: String::from(language_code),
+            display_name: Some(String::from(display_name)),
+        });
+        self
+    }
+
+    /// Detach by language, for delete
+    pub fn language(mut self, language_code: &str) -> Subtitles {
+        self.subtitle_info.subtitles.push(Subtitle {
+            media_id: None,
+            language_code: String::from(language_code),
+            display_name: None,
+        });
+        self
+    }
+
+    pub fn to_value(&self) -> Result<serde_json::Value, TwapiError> {
+        Ok(serde_json::to_value(self)?)
+    }
+
+    pub async fn create<T: Twapi + Sync + ?Sized>(
+        &self,
+        twapi: &T,
+    ) -> Result<TwapiResponse, TwapiError> {
+        twapi
+            .post_media_subtitles_create(&self.to_value()?)
+            .await?
+            .into_result()
+    }
+
+    pub async fn delete<T: Twapi + Sync + ?Sized>(
+        &self,
+        twapi: &T,
+    ) -> Result<TwapiResponse, TwapiError> {
+        twapi
+            .post_media_subtitles_delete(&self.to_value()?)
+            .await?
+            .into_result()
+    }
+}
+
+/// MediaUploader for SRT files
+pub fn subtitles_uploader() -> MediaUploader {
+    MediaUploader::new(SUBTITLES_MEDIA_TYPE, SUBTITLES_MEDIA_CATEGORY)
+}
+
+/// Upload SRT file and attach it to the video
+pub async fn upload_subtitles<T: Twapi + Sync + ?Sized>(
+    twapi: &T,
+    video_media_id: &str,
+    file: &str,
+    language_code: &str,
+    display_name: &str,
+) -> Result<TwapiResponse, TwapiError> {
+    let result = subtitles_uploader().upload(twapi, file).await?;
+    let subtitle_media_id = media_id_of(&result)?;
+    Subtitles::new(video_media_id)
+        .subtitle(&subtitle_media_id, language_code, display_name)
+        .create(twapi)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockResponse, MockTwapi};
+    use serde_json::json;
+
+    #[test]
+    fn limits_alt_text_characters() {
+        let alt_text = "あ".repeat(MAX_ALT_TEXT_LENGTH);
+        assert_eq!(
+            MediaMetadata::new("1").alt_text(&alt_text).validate(),
+            Ok(())
+        );
+        let alt_text = "a".repeat(MAX_ALT_TEXT_LENGTH + 1);
+        assert_eq!(
+            MediaMetadata::new("1").alt_text(&alt_text).validate(),
+            Err(MediaError::AltTextTooLong {
+                length: MAX_ALT_TEXT_LENGTH + 1,
+                max_length: MAX_ALT_TEXT_LENGTH,
+            })
+        );
+    }
+
+    #[test]
+    fn serializes_metadata() {
+        assert_eq!(
+            MediaMetadata::new("1").to_value().unwrap(),
+            json!({"media_id": "1"})
+        );
+        let metadata = MediaMetadata::new("1")
+            .alt_text("a cat")
+            .sensitive_media_warning(SensitiveMediaWarning::AdultContent)
+            .sensitive_media_warning(SensitiveMediaWarning::GraphicViolence)
+            .sensitive_media_warning(SensitiveMediaWarning::AdultContent)
+            .sensitive_media_warning(SensitiveMediaWarning::Other);
+        assert_eq!(
+            metadata.to_value().unwrap(),
+            json!({
+                "media_id": "1",
+                "alt_text": {"text": "a cat"},
+                "sensitive_media_warning": ["adult_content", "graphic_violence", "other"]
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn creates_metadata() {
+        let twapi = MockTwapi::new();
+        let metadata = MediaMetadata::new("1").alt_text("a cat");
+        metadata.create(&twapi).await.unwrap();
+        let requests = twapi.requests();
+        assert_eq!(
+            requests[0].uri,
+            "https://upload.twitter.com/1.1/media/metadata/create.json"
+        );
+        assert_eq!(requests[0].json, Some(metadata.to_value().unwrap()));
+
+        let metadata = MediaMetadata::new("1").alt_text(&"a".repeat(MAX_ALT_TEXT_LENGTH + 1));
+        assert!(metadata.create(&twapi).await.is_err());
+        assert_eq!(twapi.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn creates_and_deletes_subtitles() {
+        let twapi = MockTwapi::new();
+        twapi
+            .push(MockResponse::new(200, json!({})))
+            .push(MockResponse::new(
+                400,
+                json!({"errors": [{"code": 324, "message": "Invalid media id."}]}),
+            ));
+        let create = Subtitles::new("10").subtitle("20", "EN", "English");
+        create.create(&twapi).await.unwrap();
+        let delete = Subtitles::new("10")
+            .media_category("AmplifyVideo")
+            .language("EN");
+        assert!(delete.delete(&twapi).await.is_err());
+
+        let requests = twapi.requests();
+        assert_eq!(
+            requests[0].uri,
+            "https://upload.twitter.com/1.1/media/subtitles/create.json"
+        );
+        assert_eq!(
+            requests[0].json,
+            Some(json!({
+                "media_id": "10",
+                "media_category": "TweetVideo",
+                "subtitle_info": {"subtitles": [
+                    {"media_id": "20", "language_code": "EN", "display_name": "English"}
+                ]}
+            }))
+        );
+        assert_eq!(
+            requests[1].uri,
+            "https://upload.twitter.com/1.1/media/subtitles/delete.json"
+        );
+        assert_eq!(
+            requests[1].json,
+            Some(json!({
+                "media_id": "10",
+                "media_category": "AmplifyVideo",
+                "subtitle_info": {"subtitles": [{"language_code": "EN"}]}
+            }))
+        );
+    }
+}
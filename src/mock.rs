@@ -0,0 +1,317 @@
+//! Twapi returning queued responses for unit tests
+#![allow(dead_code)]
+use super::{TResult, Twapi};
+use async_trait::async_trait;
+use std::{collections::VecDeque, sync::Mutex};
+use twapi_reqwest::reqwest::{multipart::Form, Response};
+
+/// Request received by MockTwapi
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MockRequest {
+    pub method: &'static str,
+    pub uri: String,
+    pub query_options: Vec<(String, String)>,
+    pub form_options: Vec<(String, String)>,
+    pub json: Option<serde_json::Value>,
+}
+
+impl MockRequest {
+    pub fn param(&self, key: &str) -> Option<&str> {
+        self.query_options
+            .iter()
+            .chain(self.form_options.iter())
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Response queued to MockTwapi
+#[derive(Debug, Clone)]
+pub(crate) struct MockResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl MockResponse {
+    pub fn new(status_code: u16, json: serde_json::Value) -> MockResponse {
+        MockResponse {
+            status_code,
+            headers: vec![],
+            body: json.to_string(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> MockResponse {
+        self.headers.push((String::from(name), String::from(value)));
+        self
+    }
+
+    fn into_response(self) -> Response {
+        let mut builder = http::Response::builder().status(self.status_code);
+        for (name, value) in self.headers.iter() {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+        Response::from(builder.body(self.body).unwrap())
+    }
+}
+
+/// Answers queued responses in order, then 200 {} when empty
+#[derive(Default)]
+pub(crate) struct MockTwapi {
+    responses: Mutex<VecDeque<MockResponse>>,
+    requests: Mutex<Vec<MockRequest>>,
+}
+
+impl MockTwapi {
+    pub fn new() -> MockTwapi {
+        MockTwapi::default()
+    }
+
+    pub fn push(&self, response: MockResponse) -> &MockTwapi {
+        self.responses.lock().unwrap().push_back(response);
+        self
+    }
+
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn respond(
+        &self,
+        method: &'static str,
+        uri: &str,
+        query_options: &[(&str, &str)],
+        form_options: &[(&str, &str)],
+        json: Option<&serde_json::Value>,
+    ) -> TResult {
+        let to_owned = |options: &[(&str, &str)]| {
+            options
+                .iter()
+                .map(|(k, v)| (String::from(*k), String::from(*v)))
+                .collect()
+        };
+        self.requests.lock().unwrap().push(MockRequest {
+            method,
+            uri: String::from(uri),
+            query_options: to_owned(query_options),
+            form_options: to_owned(form_options),
+            json: json.cloned(),
+        });
+        let response = self
+            .responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or_else(|| MockResponse::new(200, serde_json::json!({})));
+        Ok(response.into_response())
+    }
+}
+
+// put_json is left to the default of Twapi
+#[async_trait]
+impl Twapi for MockTwapi {
+    async fn get(&self, uri: &str, query_options: &Vec<(&str, &str)>) -> TResult {
+        self.respond("GET", uri, query_options, &[], None)
+    }
+
+    async fn post(
+        &self,
+        uri: &str,
+        query_options: &Vec<(&str, &str)>,
+        form_options: &Vec<(&str, &str)>,
+    ) -> TResult {
+        self.respond("POST", uri, query_options, form_options, None)
+    }
+
+    async fn multipart(
+        &self,
+        uri: &str,
+        query_options: &Vec<(&str, &str)>,
+        _form: Form,
+    ) -> TResult {
+        self.respond("MULTIPART", uri, query_options, &[], None)
+    }
+
+    async fn put(&self, uri: &str, query_options: &Vec<(&str, &str)>) -> TResult {
+        self.respond("PUT", uri, query_options, &[], None)
+    }
+
+    async fn delete(&self, uri: &str, query_options: &Vec<(&str, &str)>) -> TResult {
+        self.respond("DELETE", uri, query_options, &[], None)
+    }
+
+    async fn json(
+        &self,
+        uri: &str,
+        query_options: &Vec<(&str, &str)>,
+        json: &serde_json::Value,
+    ) -> TResult {
+        self.respond("JSON", uri, query_options, &[], Some(json))
+    }
+}
+
+/// Request received by MockServer
+#[derive(Debug, Clone)]
+pub(crate) struct ServerRequest {
+    pub request_line: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl ServerRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Name and data of each part of multipart/form-data body
+    pub fn multipart(&self) -> Vec<(String, Vec<u8>)> {
+        let boundary = match self
+            .header("content-type")
+            .and_then(|it| it.split("boundary=").nth(1))
+        {
+            Some(boundary) => format!("--{}", boundary),
+            None => return vec![],
+        };
+        let mut parts = vec![];
+        for part in split_bytes(&self.body, boundary.as_bytes())
+            .into_iter()
+            .skip(1)
+        {
+            let part = part.strip_prefix(b"\r\n").unwrap_or(part);
+            let header_end = match find_bytes(part, b"\r\n\r\n") {
+                Some(header_end) => header_end,
+                None => continue,
+            };
+            let headers = String::from_utf8_lossy(&part[..header_end]);
+            let name = headers
+                .split("name=\"")
+                .nth(1)
+                .and_then(|it| it.split('"').next())
+                .unwrap_or_default();
+            let data = &part[header_end + 4..];
+            let data = data.strip_suffix(b"\r\n").unwrap_or(data);
+            parts.push((String::from(name), data.to_vec()));
+        }
+        parts
+    }
+
+    /// Text of the first part named name
+    pub fn part(&self, name: &str) -> Option<String> {
+        self.multipart()
+            .into_iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| String::from_utf8_lossy(&v).into_owned())
+    }
+}
+
+/// HTTP server on 127.0.0.1 answering one queued response per connection,
+/// for tests through the real transport of ApplicationAuth and UserAuth
+pub(crate) struct MockServer {
+    base_url: String,
+    handle: std::thread::JoinHandle<Vec<ServerRequest>>,
+}
+
+impl MockServer {
+    pub fn start(responses: Vec<MockResponse>) -> MockServer {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            responses
+                .into_iter()
+                .map(|response| {
+                    let (stream, _) = listener.accept().unwrap();
+                    serve(stream, response)
+                })
+                .collect()
+        });
+        MockServer { base_url, handle }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Wait until every response is sent
+    pub fn requests(self) -> Vec<ServerRequest> {
+        self.handle.join().unwrap()
+    }
+}
+
+fn serve(stream: std::net::TcpStream, response: MockResponse) -> ServerRequest {
+    use std::io::{BufRead, BufReader, Read, Write};
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    let mut headers = vec![];
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_lowercase(), String::from(value.trim())));
+        }
+    }
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v): &(String, String)| v.clone())
+    };
+    let mut body = vec![];
+    if let Some(length) = header("content-length") {
+        body.resize(length.parse().unwrap(), 0);
+        reader.read_exact(&mut body).unwrap();
+    } else if header("transfer-encoding").as_deref() == Some("chunked") {
+        loop {
+            let mut size = String::new();
+            reader.read_line(&mut size).unwrap();
+            let size = usize::from_str_radix(size.trim(), 16).unwrap();
+            let mut chunk = vec![0; size + 2];
+            reader.read_exact(&mut chunk).unwrap();
+            if size == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..size]);
+        }
+    }
+    let mut head = format!(
+        "HTTP/1.1 {} OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n",
+        response.status_code,
+        response.body.len()
+    );
+    for (name, value) in response.headers.iter() {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    let mut stream = stream;
+    stream.write_all(head.as_bytes()).unwrap();
+    stream.write_all(response.body.as_bytes()).unwrap();
+    ServerRequest {
+        request_line: String::from(request_line.trim_end()),
+        headers,
+        body,
+    }
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn split_bytes<'a>(mut bytes: &'a [u8], separator: &[u8]) -> Vec<&'a [u8]> {
+    let mut result = vec![];
+    while let Some(position) = find_bytes(bytes, separator) {
+        result.push(&bytes[..position]);
+        bytes = &bytes[position + separator.len()..];
+    }
+    result.push(bytes);
+    result
+}
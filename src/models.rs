@@ -0,0 +1,645 @@
+//! Typed models of Twitter API v1.1 objects
+//!
+//! Every field has a default, so partial objects such as trimmed users
+//! or webhook payloads can be deserialized.
+use super::{Twapi, TwapiError};
+use async_trait::async_trait;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+
+/// Tweet object
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tweet {
+    pub created_at: String,
+    pub id: u64,
+    pub id_str: String,
+    pub text: Option<String>,
+    pub full_text: Option<String>,
+    pub truncated: bool,
+    pub display_text_range: Option<[usize; 2]>,
+    pub entities: Entities,
+    pub extended_entities: Option<ExtendedEntities>,
+    pub extended_tweet: Option<ExtendedTweet>,
+    pub source: Option<String>,
+    pub in_reply_to_status_id_str: Option<String>,
+    pub in_reply_to_user_id_str: Option<String>,
+    pub in_reply_to_screen_name: Option<String>,
+    pub user: Option<User>,
+    pub place: Option<Place>,
+    pub is_quote_status: bool,
+    pub quoted_status_id_str: Option<String>,
+    pub quoted_status: Option<Box<Tweet>>,
+    pub retweeted_status: Option<Box<Tweet>>,
+    pub quote_count: Option<u64>,
+    pub reply_count: Option<u64>,
+    pub retweet_count: u64,
+    pub favorite_count: Option<u64>,
+    pub favorited: Option<bool>,
+    pub retweeted: Option<bool>,
+    pub possibly_sensitive: Option<bool>,
+    pub lang: Option<String>,
+}
+
+impl Tweet {
+    /// full_text, extended_tweet.full_text or text
+    pub fn full_text(&self) -> &str {
+        self.full_text
+            .as_deref()
+            .or_else(|| self.extended_tweet.as_ref().map(|it| it.full_text.as_str()))
+            .or(self.text.as_deref())
+            .unwrap_or_default()
+    }
+}
+
+/// extended_tweet of compatibility mode tweets
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExtendedTweet {
+    pub full_text: String,
+    pub display_text_range: Option<[usize; 2]>,
+    pub entities: Entities,
+    pub extended_entities: Option<ExtendedEntities>,
+}
+
+/// User object
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct User {
+    /// direct message users map has string id
+    #[serde(deserialize_with = "string_or_u64")]
+    pub id: u64,
+    pub id_str: String,
+    pub name: String,
+    pub screen_name: String,
+    pub location: Option<String>,
+    pub url: Option<String>,
+    pub description: Option<String>,
+    pub protected: bool,
+    pub verified: bool,
+    pub followers_count: u64,
+    pub friends_count: u64,
+    pub listed_count: u64,
+    pub favourites_count: u64,
+    pub statuses_count: u64,
+    pub created_at: String,
+    pub profile_image_url_https: Option<String>,
+    pub profile_banner_url: Option<String>,
+    pub default_profile: bool,
+    pub default_profile_image: bool,
+}
+
+/// Entities object
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Entities {
+    pub hashtags: Vec<Hashtag>,
+    pub symbols: Vec<Hashtag>,
+    pub user_mentions: Vec<UserMention>,
+    pub urls: Vec<Url>,
+    pub media: Vec<Media>,
+}
+
+/// extended_entities object
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExtendedEntities {
+    pub media: Vec<Media>,
+}
+
+/// Hashtag and symbol(cashtag) entity
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Hashtag {
+    pub text: String,
+    pub indices: [usize; 2],
+}
+
+/// User mention entity
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserMention {
+    pub id_str: String,
+    pub screen_name: String,
+    pub name: String,
+    pub indices: [usize; 2],
+}
+
+/// URL entity
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Url {
+    pub url: String,
+    pub expanded_url: Option<String>,
+    pub display_url: Option<String>,
+    pub indices: [usize; 2],
+}
+
+/// Media entity
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Media {
+    pub id: u64,
+    pub id_str: String,
+    #[serde(rename = "type")]
+    pub media_type: String,
+    pub url: String,
+    pub display_url: String,
+    pub expanded_url: String,
+    pub media_url_https: String,
+    pub indices: [usize; 2],
+    pub sizes: HashMap<String, MediaSize>,
+    pub video_info: Option<VideoInfo>,
+    pub ext_alt_text: Option<String>,
+    pub source_status_id_str: Option<String>,
+}
+
+/// Size of media
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MediaSize {
+    pub w: u64,
+    pub h: u64,
+    pub resize: String,
+}
+
+/// video_info of video and animated_gif
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VideoInfo {
+    pub aspect_ratio: Vec<u64>,
+    pub duration_millis: Option<u64>,
+    pub variants: Vec<VideoVariant>,
+}
+
+/// One encoding of video
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VideoVariant {
+    pub bitrate: Option<u64>,
+    pub content_type: String,
+    pub url: String,
+}
+
+/// Place object
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Place {
+    pub id: String,
+    pub url: String,
+    pub place_type: String,
+    pub name: String,
+    pub full_name: String,
+    pub country_code: String,
+    pub country: String,
+    pub bounding_box: Option<BoundingBox>,
+}
+
+/// bounding_box of place
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BoundingBox {
+    #[serde(rename = "type")]
+    pub box_type: String,
+    pub coordinates: Vec<Vec<[f64; 2]>>,
+}
+
+/// Direct message event object
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DirectMessageEvent {
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub id: String,
+    pub created_timestamp: String,
+    pub message_create: Option<MessageCreate>,
+}
+
+/// message_create of direct message event
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessageCreate {
+    pub target: MessageTarget,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sender_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_app_id: Option<String>,
+    pub message_data: MessageData,
+}
+
+/// target of message_create
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessageTarget {
+    pub recipient_id: String,
+}
+
+/// message_data of direct message and welcome message
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessageData {
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entities: Option<Entities>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quick_reply: Option<QuickReply>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quick_reply_response: Option<QuickReplyResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attachment: Option<Attachment>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ctas: Option<Vec<Cta>>,
+}
+
+/// quick_reply of message_data
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuickReply {
+    #[serde(rename = "type")]
+    pub quick_reply_type: String,
+    pub options: Vec<QuickReplyOption>,
+}
+
+/// Option of quick_reply
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuickReplyOption {
+    pub label: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<String>,
+}
+
+/// quick_reply_response of received message_data
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuickReplyResponse {
+    #[serde(rename = "type")]
+    pub response_type: String,
+    pub metadata: Option<String>,
+}
+
+/// attachment of message_data
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Attachment {
+    #[serde(rename = "type")]
+    pub attachment_type: String,
+    pub media: AttachmentMedia,
+}
+
+/// media of attachment, only id is sent
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AttachmentMedia {
+    /// number in received events
+    #[serde(deserialize_with = "string_or_number")]
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub media_url_https: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", rename = "type")]
+    pub media_type: Option<String>,
+}
+
+/// Call-to-action button of message_data
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Cta {
+    #[serde(rename = "type")]
+    pub cta_type: String,
+    pub label: String,
+    pub url: String,
+}
+
+/// Welcome message object
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WelcomeMessage {
+    pub id: String,
+    pub created_timestamp: String,
+    pub name: Option<String>,
+    pub message_data: MessageData,
+}
+
+/// Welcome message rule object, the default welcome message
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WelcomeMessageRule {
+    pub id: String,
+    pub created_timestamp: String,
+    pub welcome_message_id: String,
+}
+
+/// Response of direct_messages/welcome_messages/rules/list
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WelcomeMessageRuleList {
+    pub welcome_message_rules: Vec<WelcomeMessageRule>,
+    pub next_cursor: Option<String>,
+}
+
+/// Response of search/tweets
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchResult {
+    pub statuses: Vec<Tweet>,
+    pub search_metadata: serde_json::Value,
+}
+
+/// Response of direct_messages/events/list
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DirectMessageEventList {
+    pub events: Vec<DirectMessageEvent>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct EventResponse {
+    event: DirectMessageEvent,
+}
+
+#[derive(Deserialize)]
+struct WelcomeMessageResponse {
+    welcome_message: WelcomeMessage,
+}
+
+#[derive(Deserialize)]
+struct WelcomeMessageListResponse {
+    #[serde(default)]
+    welcome_messages: Vec<WelcomeMessage>,
+}
+
+#[derive(Deserialize)]
+struct WelcomeMessageRuleResponse {
+    welcome_message_rule: WelcomeMessageRule,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StringOrU64 {
+    String(String),
+    U64(u64),
+}
+
+fn string_or_u64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    match StringOrU64::deserialize(deserializer)? {
+        StringOrU64::String(it) => it.parse().map_err(serde::de::Error::custom),
+        StringOrU64::U64(it) => Ok(it),
+    }
+}
+
+fn string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    match StringOrU64::deserialize(deserializer)? {
+        StringOrU64::String(it) => Ok(it),
+        StringOrU64::U64(it) => Ok(it.to_string()),
+    }
+}
+
+/// Typed variants of Twapi helpers
+#[allow(clippy::ptr_arg)]
+#[async_trait]
+pub trait TwapiTyped: Twapi {
+    async fn get_verify_credentials_typed(
+        &self,
+        params: &Vec<(&str, &str)>,
+    ) -> Result<User, TwapiError> {
+        self.get_verify_credentials(params).await?.parse()
+    }
+
+    async fn get_search_tweets_typed(
+        &self,
+        params: &Vec<(&str, &str)>,
+    ) -> Result<SearchResult, TwapiError> {
+        self.get_search_tweets(params).await?.parse()
+    }
+
+    async fn post_statuses_update_typed(
+        &self,
+        params: &Vec<(&str, &str)>,
+    ) -> Result<Tweet, TwapiError> {
+        self.post_statuses_update(params).await?.parse()
+    }
+
+    async fn post_direct_messages_events_new_typed(
+        &self,
+        value: &serde_json::Value,
+    ) -> Result<DirectMessageEvent, TwapiError> {
+        let res: EventResponse = self.post_direct_messages_events_new(value).await?.parse()?;
+        Ok(res.event)
+    }
+
+    async fn get_direct_messages_events_list_typed(
+        &self,
+        params: &Vec<(&str, &str)>,
+    ) -> Result<DirectMessageEventList, TwapiError> {
+        self.get_direct_messages_events_list(params).await?.parse()
+    }
+
+    async fn get_direct_messages_events_show_typed(
+        &self,
+        id: &str,
+    ) -> Result<DirectMessageEvent, TwapiError> {
+        let res: EventResponse = self.get_direct_messages_events_show(id).await?.parse()?;
+        Ok(res.event)
+    }
+
+    async fn get_direct_messages_welcome_messages_list_typed(
+        &self,
+    ) -> Result<Vec<WelcomeMessage>, TwapiError> {
+        let res: WelcomeMessageListResponse = self
+            .get_direct_messages_welcome_messages_list()
+            .await?
+            .parse()?;
+        Ok(res.welcome_messages)
+    }
+
+    async fn get_direct_messages_welcome_messages_show_typed(
+        &self,
+        id: &str,
+    ) -> Result<WelcomeMessage, TwapiError> {
+        let res: WelcomeMessageResponse = self
+            .get_direct_messages_welcome_messages_show(id)
+            .await?
+            .parse()?;
+        Ok(res.welcome_message)
+    }
+
+    async fn post_direct_messages_welcome_messages_new_typed(
+        &self,
+        value: &serde_json::Value,
+    ) -> Result<WelcomeMessage, TwapiError> {
+        let res: WelcomeMessageResponse = self
+            .post_direct_messages_welcome_messages_new(value)
+            .await?
+            .parse()?;
+        Ok(res.welcome_message)
+    }
+
+    async fn put_direct_messages_welcome_messages_update_typed(
+        &self,
+        id: &str,
+        value: &serde_json::Value,
+    ) -> Result<WelcomeMessage, TwapiError> {
+        let res: WelcomeMessageResponse = self
+            .put_direct_messages_welcome_messages_update(id, value)
+            .await?
+            .parse()?;
+        Ok(res.welcome_message)
+    }
+
+    async fn post_direct_messages_welcome_messages_rules_new_typed(
+        &self,
+        welcome_message_id: &str,
+    ) -> Result<WelcomeMessageRule, TwapiError> {
+        let res: WelcomeMessageRuleResponse = self
+            .post_direct_messages_welcome_messages_rules_new(welcome_message_id)
+            .await?
+            .parse()?;
+        Ok(res.welcome_message_rule)
+    }
+
+    async fn get_direct_messages_welcome_messages_rules_list_typed(
+        &self,
+        params: &Vec<(&str, &str)>,
+    ) -> Result<WelcomeMessageRuleList, TwapiError> {
+        self.get_direct_messages_welcome_messages_rules_list(params)
+            .await?
+            .parse()
+    }
+
+    async fn get_direct_messages_welcome_messages_rules_show_typed(
+        &self,
+        id: &str,
+    ) -> Result<WelcomeMessageRule, TwapiError> {
+        let res: WelcomeMessageRuleResponse = self
+            .get_direct_messages_welcome_messages_rules_show(id)
+            .await?
+            .parse()?;
+        Ok(res.welcome_message_rule)
+    }
+}
+
+impl<T: Twapi + Sync + ?Sized> TwapiTyped for T {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockResponse, MockTwapi};
+    use serde_json::json;
+
+    #[test]
+    fn user_id_is_number_or_string() {
+        let user: User = serde_json::from_value(json!({"id": 12, "id_str": "12"})).unwrap();
+        assert_eq!(user.id, 12);
+        let user: User =
+            serde_json::from_value(json!({"id": "18446744073709551615", "screen_name": "a"}))
+                .unwrap();
+        assert_eq!(user.id, u64::MAX);
+        assert_eq!(user.screen_name, "a");
+        let user: User = serde_json::from_value(json!({"id_str": "12"})).unwrap();
+        assert_eq!(user.id, 0);
+        assert!(serde_json::from_value::<User>(json!({"id": "abc"})).is_err());
+    }
+
+    #[test]
+    fn tweet_with_extended_tweet() {
+        let tweet: Tweet = serde_json::from_value(json!({
+            "created_at": "Fri Mar 26 00:00:00 +0000 2021",
+            "id": 1374000000000000000u64,
+            "id_str": "1374000000000000000",
+            "text": "truncated… https://t.co/x",
+            "truncated": true,
+            "user": {"id": 12, "id_str": "12", "screen_name": "a"},
+            "entities": {"urls": [{"url": "https://t.co/x", "indices": [11, 25]}]},
+            "extended_tweet": {
+                "full_text": "full text #rust https://t.co/m",
+                "display_text_range": [0, 15],
+                "entities": {
+                    "hashtags": [{"text": "rust", "indices": [10, 15]}],
+                    "media": [{"id": 5, "id_str": "5", "type": "photo", "indices": [16, 30]}]
+                },
+                "extended_entities": {
+                    "media": [{"id": 5, "id_str": "5", "type": "photo", "indices": [16, 30]}]
+                }
+            },
+            "quoted_status": {"id": 2, "id_str": "2", "full_text": "quoted"},
+            "retweet_count": 3,
+            "unknown_field": true
+        }))
+        .unwrap();
+        assert_eq!(tweet.id, 1374000000000000000);
+        assert!(tweet.truncated);
+        assert_eq!(tweet.full_text(), "full text #rust https://t.co/m");
+        assert_eq!(tweet.user.unwrap().id, 12);
+        assert_eq!(tweet.entities.urls[0].indices, [11, 25]);
+        let extended = tweet.extended_tweet.unwrap();
+        assert_eq!(extended.display_text_range, Some([0, 15]));
+        assert_eq!(extended.entities.hashtags[0].text, "rust");
+        assert_eq!(extended.entities.media[0].media_type, "photo");
+        assert_eq!(extended.extended_entities.unwrap().media.len(), 1);
+        assert_eq!(tweet.quoted_status.unwrap().full_text(), "quoted");
+        assert_eq!(tweet.retweet_count, 3);
+        assert_eq!(tweet.favorite_count, None);
+    }
+
+    #[test]
+    fn direct_message_event() {
+        let event: DirectMessageEvent = serde_json::from_value(json!({
+            "type": "message_create",
+            "id": "110",
+            "created_timestamp": "1616716800000",
+            "message_create": {
+                "target": {"recipient_id": "100"},
+                "sender_id": "200",
+                "source_app_id": "300",
+                "message_data": {
+                    "text": "Yes https://t.co/m",
+                    "entities": {"hashtags": [], "urls": []},
+                    "quick_reply_response": {"type": "options", "metadata": "yes"},
+                    "attachment": {
+                        "type": "media",
+                        "media": {"id": 5, "id_str": "5", "media_url_https": "https://pbs.twimg.com/5.jpg", "type": "photo"}
+                    }
+                }
+            }
+        }))
+        .unwrap();
+        assert_eq!(event.event_type, "message_create");
+        assert_eq!(event.id, "110");
+        let message_create = event.message_create.unwrap();
+        assert_eq!(message_create.target.recipient_id, "100");
+        assert_eq!(message_create.sender_id.as_deref(), Some("200"));
+        let data = message_create.message_data;
+        assert_eq!(data.text, "Yes https://t.co/m");
+        assert_eq!(
+            data.quick_reply_response.unwrap().metadata.as_deref(),
+            Some("yes")
+        );
+        let media = data.attachment.unwrap().media;
+        assert_eq!(media.id, "5");
+        assert_eq!(media.media_type.as_deref(), Some("photo"));
+        assert_eq!(
+            media.media_url_https.as_deref(),
+            Some("https://pbs.twimg.com/5.jpg")
+        );
+    }
+
+    #[tokio::test]
+    async fn typed_helpers_parse_responses() {
+        let twapi = MockTwapi::new();
+        twapi
+            .push(MockResponse::new(200, json!({"id": 12, "id_str": "12"})))
+            .push(MockResponse::new(
+                401,
+                json!({"errors": [{"code": 89, "message": "Invalid or expired token."}]}),
+            ));
+        let user = twapi.get_verify_credentials_typed(&vec![]).await.unwrap();
+        assert_eq!(user.id_str, "12");
+        match twapi.get_verify_credentials_typed(&vec![]).await {
+            Err(TwapiError::Api(err)) => assert!(err.has(crate::ApiErrorKind::InvalidToken)),
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+}
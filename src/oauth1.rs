@@ -1,5 +1,5 @@
 //! User Authentication OAuth1
-use super::TwapiError;
+use super::{trim_base_url, TwapiError, API_BASE_URL};
 
 /// OAuth requet token
 /// Return oauth_token, oauth_token_secret, uri
@@ -28,14 +28,20 @@ pub async fn request_token(
             return Err(TwapiError::NotExists);
         }
     };
-    let uri = format!(
-        "http://api.twitter.com/oauth/authorize?oauth_token={}",
-        oauth_token
-    );
+    let uri = authorize_uri(API_BASE_URL, oauth_token);
 
     Ok((oauth_token.to_string(), oauth_token_secret.to_string(), uri))
 }
 
+/// Page to authorize oauth_token of request_token on api_base_url
+pub fn authorize_uri(api_base_url: &str, oauth_token: &str) -> String {
+    format!(
+        "{}/oauth/authorize?oauth_token={}",
+        trim_base_url(api_base_url),
+        oauth_token
+    )
+}
+
 /// OAuth access token
 /// Return oauth_token, oauth_token_secret, user_id, screen_name
 pub async fn access_token(
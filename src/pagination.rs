@@ -0,0 +1,634 @@
+//! Pagination for v1.1 cursored endpoints and max_id/since_id timelines
+use super::{Twapi, TwapiError, TwapiResponse};
+use futures_util::stream::{self, Stream, StreamExt};
+
+/// Stream each page of a cursored endpoint.
+/// Follow next_cursor_str (or next_cursor) until it is zero or missing.
+pub fn cursor_pages<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    uri: &str,
+    params: &[(&str, &str)],
+    max_pages: Option<usize>,
+) -> impl Stream<Item = Result<serde_json::Value, TwapiError>> + Send + 'a {
+    token_pages(twapi, uri, params, "cursor", next_cursor, max_pages)
+}
+
+/// Stream each page, passing the token that next_token extracts from a page
+/// as token_param of the next request until it is None
+pub(crate) fn token_pages<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    uri: &str,
+    params: &[(&str, &str)],
+    token_param: &str,
+    next_token: fn(&serde_json::Value) -> Option<String>,
+    max_pages: Option<usize>,
+) -> impl Stream<Item = Result<serde_json::Value, TwapiError>> + Send + 'a {
+    let uri = String::from(uri);
+    let params = to_owned_params(params);
+    let token_param = String::from(token_param);
+    // None is finished, Some(None) is the first page
+    stream::unfold((Some(None), 0), move |(token, pages)| {
+        let uri = uri.clone();
+        let params = params.clone();
+        let token_param = token_param.clone();
+        async move {
+            let token: Option<String> = token?;
+            if matches!(max_pages, Some(max_pages) if pages >= max_pages) {
+                return None;
+            }
+            let mut query = to_query(&params);
+            if let Some(ref token) = token {
+                query.push((&token_param, token));
+            }
+            match get_json(twapi, &uri, &query).await {
+                Ok(json) => {
+                    let next = next_token(&json);
+                    Some((Ok(json), (next.map(Some), pages + 1)))
+                }
+                Err(err) => Some((Err(err), (None, pages + 1))),
+            }
+        }
+    })
+}
+
+/// Stream each item of items_key array in the pages of a cursored endpoint
+pub fn cursor_items<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    uri: &str,
+    params: &[(&str, &str)],
+    items_key: &str,
+    max_pages: Option<usize>,
+) -> impl Stream<Item = Result<serde_json::Value, TwapiError>> + Send + 'a {
+    let items_key = String::from(items_key);
+    cursor_pages(twapi, uri, params, max_pages)
+        .flat_map(move |page| stream::iter(page_items(page, &items_key)))
+}
+
+/// followers/ids
+pub fn followers_ids_stream<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    params: &[(&str, &str)],
+    max_pages: Option<usize>,
+) -> impl Stream<Item = Result<serde_json::Value, TwapiError>> + Send + 'a {
+    let uri = format!("{}/1.1/followers/ids.json", twapi.api_base_url());
+    cursor_items(twapi, &uri, params, "ids", max_pages)
+}
+
+/// followers/list
+pub fn followers_list_stream<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    params: &[(&str, &str)],
+    max_pages: Option<usize>,
+) -> impl Stream<Item = Result<serde_json::Value, TwapiError>> + Send + 'a {
+    let uri = format!("{}/1.1/followers/list.json", twapi.api_base_url());
+    cursor_items(twapi, &uri, params, "users", max_pages)
+}
+
+/// friends/ids
+pub fn friends_ids_stream<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    params: &[(&str, &str)],
+    max_pages: Option<usize>,
+) -> impl Stream<Item = Result<serde_json::Value, TwapiError>> + Send + 'a {
+    let uri = format!("{}/1.1/friends/ids.json", twapi.api_base_url());
+    cursor_items(twapi, &uri, params, "ids", max_pages)
+}
+
+/// friends/list
+pub fn friends_list_stream<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    params: &[(&str, &str)],
+    max_pages: Option<usize>,
+) -> impl Stream<Item = Result<serde_json::Value, TwapiError>> + Send + 'a {
+    let uri = format!("{}/1.1/friends/list.json", twapi.api_base_url());
+    cursor_items(twapi, &uri, params, "users", max_pages)
+}
+
+/// lists/members
+pub fn lists_members_stream<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    params: &[(&str, &str)],
+    max_pages: Option<usize>,
+) -> impl Stream<Item = Result<serde_json::Value, TwapiError>> + Send + 'a {
+    let uri = format!("{}/1.1/lists/members.json", twapi.api_base_url());
+    cursor_items(twapi, &uri, params, "users", max_pages)
+}
+
+/// direct_messages/events/list
+pub fn direct_messages_events_stream<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    params: &[(&str, &str)],
+    max_pages: Option<usize>,
+) -> impl Stream<Item = Result<serde_json::Value, TwapiError>> + Send + 'a {
+    let uri = format!(
+        "{}/1.1/direct_messages/events/list.json",
+        twapi.api_base_url()
+    );
+    cursor_items(twapi, &uri, params, "events", max_pages)
+}
+
+/// Order of timeline pagination
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimelineDirection {
+    /// Newest first, walking backwards by max_id
+    #[default]
+    Backward,
+    /// Oldest first after since_id, advancing since_id to the highest id seen
+    Forward,
+}
+
+/// Options of max_id/since_id timeline pagination
+#[derive(Debug, Clone, Default)]
+pub struct TimelineOptions {
+    /// Start from this id(inclusive) and walk backwards, latest if None
+    pub max_id: Option<String>,
+    /// Stop before this id(exclusive), so tweets newer than it are yielded
+    pub since_id: Option<String>,
+    /// Maximum number of tweets
+    pub limit: Option<usize>,
+    /// Maximum number of requests
+    pub max_pages: Option<usize>,
+    pub direction: TimelineDirection,
+}
+
+/// Stream tweets of a timeline endpoint.
+/// Backward walks by max_id from the newest, bounded by since_id when given.
+/// Forward repeats rounds of the backward walk down to since_id, yields each round
+/// oldest first and continues after its highest id until a round is empty.
+/// A round is buffered, and is cut short at max_pages leaving tweets below it unread.
+/// items_key is "statuses" for search/tweets, None for array responses.
+pub fn timeline_stream<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    uri: &str,
+    params: &[(&str, &str)],
+    items_key: Option<&str>,
+    options: TimelineOptions,
+) -> impl Stream<Item = Result<serde_json::Value, TwapiError>> + Send + 'a {
+    let limit = options.limit.unwrap_or(usize::MAX);
+    let page = TimelinePage {
+        uri: String::from(uri),
+        params: to_owned_params(params),
+        items_key: items_key.map(String::from),
+    };
+    match options.direction {
+        TimelineDirection::Backward => backward_timeline(twapi, page, options).left_stream(),
+        TimelineDirection::Forward => forward_timeline(twapi, page, options).right_stream(),
+    }
+    .take(limit)
+}
+
+#[derive(Clone)]
+struct TimelinePage {
+    uri: String,
+    params: Vec<(String, String)>,
+    items_key: Option<String>,
+}
+
+impl TimelinePage {
+    async fn get<T: Twapi + Sync + ?Sized>(
+        &self,
+        twapi: &T,
+        max_id: Option<&str>,
+        since_id: Option<&str>,
+    ) -> Result<Vec<serde_json::Value>, TwapiError> {
+        let mut query = to_query(&self.params);
+        if let Some(max_id) = max_id {
+            query.push(("max_id", max_id));
+        }
+        if let Some(since_id) = since_id {
+            query.push(("since_id", since_id));
+        }
+        let json = get_json(twapi, &self.uri, &query).await?;
+        Ok(take_items(json, self.items_key.as_deref()))
+    }
+}
+
+fn tweet_ids(items: &[serde_json::Value]) -> impl Iterator<Item = u64> + '_ {
+    items
+        .iter()
+        .filter_map(|item| item.get("id_str")?.as_str()?.parse::<u64>().ok())
+}
+
+fn backward_timeline<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    page: TimelinePage,
+    options: TimelineOptions,
+) -> impl Stream<Item = Result<serde_json::Value, TwapiError>> + Send + 'a {
+    let since_id = options.since_id;
+    let max_pages = options.max_pages;
+    // None is finished
+    stream::unfold((Some(options.max_id), 0), move |(max_id, pages)| {
+        let page = page.clone();
+        let since_id = since_id.clone();
+        async move {
+            let max_id: Option<String> = max_id?;
+            if matches!(max_pages, Some(max_pages) if pages >= max_pages) {
+                return None;
+            }
+            let items = match page
+                .get(twapi, max_id.as_deref(), since_id.as_deref())
+                .await
+            {
+                Ok(items) => items,
+                Err(err) => return Some((vec![Err(err)], (None, pages + 1))),
+            };
+            let next = tweet_ids(&items)
+                .min()
+                .and_then(|min_id| decrement_id(&min_id.to_string()));
+            let items: Vec<_> = items.into_iter().map(Ok).collect();
+            Some((items, (next.map(Some), pages + 1)))
+        }
+    })
+    .flat_map(stream::iter)
+}
+
+fn forward_timeline<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    page: TimelinePage,
+    options: TimelineOptions,
+) -> impl Stream<Item = Result<serde_json::Value, TwapiError>> + Send + 'a {
+    let top_id = options.max_id;
+    let max_pages = options.max_pages;
+    // None is finished
+    stream::unfold((Some(options.since_id), 0), move |(since_id, mut pages)| {
+        let page = page.clone();
+        let top_id = top_id.clone();
+        async move {
+            let since_id: Option<String> = since_id?;
+            let mut round = vec![];
+            let mut max_id = top_id;
+            while !matches!(max_pages, Some(max_pages) if pages >= max_pages) {
+                let items = match page
+                    .get(twapi, max_id.as_deref(), since_id.as_deref())
+                    .await
+                {
+                    Ok(items) => items,
+                    Err(err) => return Some((vec![Err(err)], (None, pages + 1))),
+                };
+                pages += 1;
+                max_id = tweet_ids(&items)
+                    .min()
+                    .and_then(|min_id| decrement_id(&min_id.to_string()));
+                round.extend(items);
+                if max_id.is_none() {
+                    break;
+                }
+            }
+            if round.is_empty() {
+                return None;
+            }
+            let next = tweet_ids(&round).max().map(|it| Some(it.to_string()));
+            round.reverse();
+            let items: Vec<_> = round.into_iter().map(Ok).collect();
+            Some((items, (next, pages)))
+        }
+    })
+    .flat_map(stream::iter)
+}
+
+/// search/tweets
+pub fn search_tweets_stream<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    params: &[(&str, &str)],
+    options: TimelineOptions,
+) -> impl Stream<Item = Result<serde_json::Value, TwapiError>> + Send + 'a {
+    let uri = format!("{}/1.1/search/tweets.json", twapi.api_base_url());
+    timeline_stream(twapi, &uri, params, Some("statuses"), options)
+}
+
+/// statuses/user_timeline
+pub fn user_timeline_stream<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    params: &[(&str, &str)],
+    options: TimelineOptions,
+) -> impl Stream<Item = Result<serde_json::Value, TwapiError>> + Send + 'a {
+    let uri = format!("{}/1.1/statuses/user_timeline.json", twapi.api_base_url());
+    timeline_stream(twapi, &uri, params, None, options)
+}
+
+/// statuses/home_timeline
+pub fn home_timeline_stream<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    params: &[(&str, &str)],
+    options: TimelineOptions,
+) -> impl Stream<Item = Result<serde_json::Value, TwapiError>> + Send + 'a {
+    let uri = format!("{}/1.1/statuses/home_timeline.json", twapi.api_base_url());
+    timeline_stream(twapi, &uri, params, None, options)
+}
+
+/// statuses/mentions_timeline
+pub fn mentions_timeline_stream<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    params: &[(&str, &str)],
+    options: TimelineOptions,
+) -> impl Stream<Item = Result<serde_json::Value, TwapiError>> + Send + 'a {
+    let uri = format!(
+        "{}/1.1/statuses/mentions_timeline.json",
+        twapi.api_base_url()
+    );
+    timeline_stream(twapi, &uri, params, None, options)
+}
+
+/// Decrement a tweet id string by one, None when it is zero or not a number
+pub fn decrement_id(id: &str) -> Option<String> {
+    id.parse::<u64>()
+        .ok()?
+        .checked_sub(1)
+        .map(|id| id.to_string())
+}
+
+pub(crate) fn to_owned_params(params: &[(&str, &str)]) -> Vec<(String, String)> {
+    params
+        .iter()
+        .map(|(key, value)| (String::from(*key), String::from(*value)))
+        .collect()
+}
+
+pub(crate) fn to_query(params: &[(String, String)]) -> Vec<(&str, &str)> {
+    params
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect()
+}
+
+pub(crate) async fn get_json<T: Twapi + Sync + ?Sized>(
+    twapi: &T,
+    uri: &str,
+    query: &Vec<(&str, &str)>,
+) -> Result<serde_json::Value, TwapiError> {
+    let res = twapi.get(uri, query).await?;
+    TwapiResponse::new(res)
+        .await
+        .into_result()?
+        .json
+        .ok_or(TwapiError::NotExists)
+}
+
+pub(crate) fn page_items(
+    page: Result<serde_json::Value, TwapiError>,
+    items_key: &str,
+) -> Vec<Result<serde_json::Value, TwapiError>> {
+    match page {
+        Ok(json) => take_items(json, Some(items_key))
+            .into_iter()
+            .map(Ok)
+            .collect(),
+        Err(err) => vec![Err(err)],
+    }
+}
+
+fn take_items(mut json: serde_json::Value, items_key: Option<&str>) -> Vec<serde_json::Value> {
+    let items = match items_key {
+        Some(items_key) => json.get_mut(items_key).map(serde_json::Value::take),
+        None => Some(json),
+    };
+    match items {
+        Some(serde_json::Value::Array(items)) => items,
+        _ => vec![],
+    }
+}
+
+fn next_cursor(json: &serde_json::Value) -> Option<String> {
+    let cursor = match json
+        .get("next_cursor_str")
+        .or_else(|| json.get("next_cursor"))?
+    {
+        serde_json::Value::String(cursor) => cursor.clone(),
+        serde_json::Value::Number(cursor) => cursor.to_string(),
+        _ => return None,
+    };
+    if cursor.is_empty() || cursor == "0" {
+        None
+    } else {
+        Some(cursor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockResponse, MockTwapi};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn follows_cursor_until_zero() {
+        let twapi = MockTwapi::new();
+        twapi
+            .push(MockResponse::new(
+                200,
+                json!({"ids": [1, 2], "next_cursor_str": "10"}),
+            ))
+            .push(MockResponse::new(
+                200,
+                json!({"ids": [3], "next_cursor_str": "0"}),
+            ));
+        let ids: Vec<_> = followers_ids_stream(&twapi, &[("count", "2")], None)
+            .map(|it| it.unwrap())
+            .collect()
+            .await;
+        assert_eq!(ids, vec![json!(1), json!(2), json!(3)]);
+        let requests = twapi.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].param("cursor"), None);
+        assert_eq!(requests[1].param("cursor"), Some("10"));
+        assert_eq!(requests[1].param("count"), Some("2"));
+    }
+
+    #[tokio::test]
+    async fn stops_at_max_pages() {
+        let twapi = MockTwapi::new();
+        twapi
+            .push(MockResponse::new(
+                200,
+                json!({"users": [{"id": 1}], "next_cursor": 10}),
+            ))
+            .push(MockResponse::new(
+                200,
+                json!({"users": [{"id": 2}], "next_cursor": 20}),
+            ));
+        let users: Vec<_> = friends_list_stream(&twapi, &[], Some(1))
+            .map(|it| it.unwrap())
+            .collect()
+            .await;
+        assert_eq!(users, vec![json!({"id": 1})]);
+        assert_eq!(twapi.requests().len(), 1);
+    }
+
+    fn tweets(ids: &[&str]) -> MockResponse {
+        let tweets: Vec<_> = ids.iter().map(|it| json!({ "id_str": it })).collect();
+        MockResponse::new(200, json!(tweets))
+    }
+
+    async fn timeline_ids(twapi: &MockTwapi, options: TimelineOptions) -> Vec<String> {
+        user_timeline_stream(twapi, &[("count", "2")], options)
+            .map(|it| String::from(it.unwrap()["id_str"].as_str().unwrap()))
+            .collect()
+            .await
+    }
+
+    fn bounds(twapi: &MockTwapi) -> Vec<(Option<String>, Option<String>)> {
+        twapi
+            .requests()
+            .iter()
+            .map(|it| {
+                assert_eq!(it.param("count"), Some("2"));
+                (
+                    it.param("max_id").map(String::from),
+                    it.param("since_id").map(String::from),
+                )
+            })
+            .collect()
+    }
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|it| String::from(*it)).collect()
+    }
+
+    fn some(id: &str) -> Option<String> {
+        Some(String::from(id))
+    }
+
+    #[tokio::test]
+    async fn walks_backwards_below_min_id_until_empty_page() {
+        let twapi = MockTwapi::new();
+        twapi
+            .push(tweets(&["30", "28"]))
+            .push(tweets(&["27", "20"]))
+            .push(tweets(&[]));
+        let options = TimelineOptions {
+            since_id: some("10"),
+            ..Default::default()
+        };
+        assert_eq!(
+            timeline_ids(&twapi, options).await,
+            ids(&["30", "28", "27", "20"])
+        );
+        assert_eq!(
+            bounds(&twapi),
+            vec![
+                (None, some("10")),
+                (some("27"), some("10")),
+                (some("19"), some("10"))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn walks_backwards_from_max_id_with_limit() {
+        let twapi = MockTwapi::new();
+        twapi
+            .push(tweets(&["30", "28"]))
+            .push(tweets(&["27", "20"]));
+        let options = TimelineOptions {
+            max_id: some("30"),
+            limit: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(
+            timeline_ids(&twapi, options).await,
+            ids(&["30", "28", "27"])
+        );
+        assert_eq!(bounds(&twapi), vec![(some("30"), None), (some("27"), None)]);
+    }
+
+    #[tokio::test]
+    async fn walks_backwards_until_max_pages() {
+        let twapi = MockTwapi::new();
+        twapi
+            .push(MockResponse::new(
+                200,
+                json!({"statuses": [{"id_str": "30"}, {"id_str": "28"}]}),
+            ))
+            .push(MockResponse::new(
+                200,
+                json!({"statuses": [{"id_str": "27"}]}),
+            ));
+        let options = TimelineOptions {
+            max_pages: Some(1),
+            ..Default::default()
+        };
+        let statuses: Vec<_> = search_tweets_stream(&twapi, &[("q", "rust")], options)
+            .map(|it| it.unwrap())
+            .collect()
+            .await;
+        assert_eq!(
+            statuses,
+            vec![json!({"id_str": "30"}), json!({"id_str": "28"})]
+        );
+        assert_eq!(twapi.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn walks_forwards_oldest_first_advancing_since_id() {
+        let twapi = MockTwapi::new();
+        twapi
+            .push(tweets(&["30", "28"]))
+            .push(tweets(&["20"]))
+            .push(tweets(&[]))
+            .push(tweets(&["31"]))
+            .push(tweets(&[]))
+            .push(tweets(&[]));
+        let options = TimelineOptions {
+            since_id: some("10"),
+            direction: TimelineDirection::Forward,
+            ..Default::default()
+        };
+        assert_eq!(
+            timeline_ids(&twapi, options).await,
+            ids(&["20", "28", "30", "31"])
+        );
+        assert_eq!(
+            bounds(&twapi),
+            vec![
+                (None, some("10")),
+                (some("27"), some("10")),
+                (some("19"), some("10")),
+                (None, some("30")),
+                (some("30"), some("30")),
+                (None, some("31")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn walks_forwards_until_max_pages() {
+        let twapi = MockTwapi::new();
+        twapi
+            .push(tweets(&["30", "28"]))
+            .push(tweets(&["27", "20"]));
+        let options = TimelineOptions {
+            since_id: some("10"),
+            max_pages: Some(2),
+            direction: TimelineDirection::Forward,
+            ..Default::default()
+        };
+        assert_eq!(
+            timeline_ids(&twapi, options).await,
+            ids(&["20", "27", "28", "30"])
+        );
+        assert_eq!(twapi.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn stops_forward_walk_on_error() {
+        let twapi = MockTwapi::new();
+        twapi
+            .push(tweets(&["30", "28"]))
+            .push(MockResponse::new(500, json!({})));
+        let options = TimelineOptions {
+            direction: TimelineDirection::Forward,
+            ..Default::default()
+        };
+        let results: Vec<_> = user_timeline_stream(&twapi, &[], options).collect().await;
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+        assert_eq!(twapi.requests().len(), 2);
+    }
+
+    #[test]
+    fn decrements_id() {
+        assert_eq!(decrement_id("100"), Some(String::from("99")));
+        assert_eq!(decrement_id("0"), None);
+        assert_eq!(decrement_id("abc"), None);
+    }
+}
@@ -0,0 +1,456 @@
+//! Render tweet text with entities to HTML or Markdown
+use super::{
+    models::{Entities, Media, Tweet},
+    TwapiError,
+};
+
+/// Output format of render
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Html,
+    Markdown,
+}
+
+/// Unit of entity indices and display_text_range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexUnit {
+    /// v1.1 tweets
+    CodePoint,
+    /// Sources counting surrogate pairs as 2, such as JavaScript
+    Utf16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderOptions {
+    pub index_unit: IndexUnit,
+    /// Append photos, videos and GIFs after the text
+    pub include_media: bool,
+    /// Render only display_text_range, hiding leading reply mentions
+    pub use_display_text_range: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> RenderOptions {
+        RenderOptions {
+            index_unit: IndexUnit::CodePoint,
+            include_media: true,
+            use_display_text_range: true,
+        }
+    }
+}
+
+enum Link {
+    Mention(String),
+    Hashtag(String),
+    Cashtag(String),
+    Url { href: String, display: String },
+    // t.co of media is removed from text
+    Media,
+}
+
+struct Span {
+    start: usize,
+    end: usize,
+    link: Link,
+}
+
+pub fn render_html(tweet: &Tweet) -> String {
+    render(tweet, Format::Html, &RenderOptions::default())
+}
+
+pub fn render_markdown(tweet: &Tweet) -> String {
+    render(tweet, Format::Markdown, &RenderOptions::default())
+}
+
+/// Tweet json such as statuses of get_search_tweets
+pub fn render_value(
+    json: &serde_json::Value,
+    format: Format,
+    options: &RenderOptions,
+) -> Result<String, TwapiError> {
+    let tweet: Tweet = serde_json::from_value(json.clone())?;
+    Ok(render(&tweet, format, options))
+}
+
+/// Text of v1.1 tweet is HTML escaped (&amp; &lt; &gt;) but indices count each as one character,
+/// so it is unescaped before applying indices and escaped again for Html
+pub fn render(tweet: &Tweet, format: Format, options: &RenderOptions) -> String {
+    let (text, display_text_range, entities, extended_entities) = match tweet.extended_tweet {
+        Some(ref extended) => (
+            extended.full_text.as_str(),
+            extended.display_text_range,
+            &extended.entities,
+            extended.extended_entities.as_ref(),
+        ),
+        None => (
+            tweet.full_text(),
+            tweet.display_text_range,
+            &tweet.entities,
+            tweet.extended_entities.as_ref(),
+        ),
+    };
+    let text = unescape_html(text);
+    let text = text.as_str();
+    let offsets = byte_offsets(text, options.index_unit);
+    let to_byte = |index: usize| offsets[index.min(offsets.len() - 1)];
+    // inverted range is ignored
+    let (start, end) = match display_text_range {
+        Some([start, end]) if options.use_display_text_range && start <= end => {
+            (to_byte(start), to_byte(end))
+        }
+        _ => (0, text.len()),
+    };
+
+    let mut result = String::new();
+    let mut position = start;
+    for span in spans(entities) {
+        let (span_start, span_end) = (to_byte(span.start), to_byte(span.end));
+        if span_start < position || span_end > end || span_start >= span_end {
+            continue;
+        }
+        result.push_str(&plain(&text[position..span_start], format));
+        result.push_str(&link(&span.link, &text[span_start..span_end], format));
+        position = span_end;
+    }
+    result.push_str(&plain(&text[position..end], format));
+    // whitespace before the removed media url
+    let mut result = String::from(result.trim_end());
+
+    if options.include_media {
+        let media = match extended_entities {
+            Some(extended) if !extended.media.is_empty() => &extended.media,
+            _ => &entities.media,
+        };
+        for media in media.iter() {
+            result.push_str(match format {
+                Format::Html => "\n",
+                Format::Markdown => "\n\n",
+            });
+            result.push_str(&render_media(media, format));
+        }
+    }
+    result.trim().to_string()
+}
+
+fn spans(entities: &Entities) -> Vec<Span> {
+    let mut spans = vec![];
+    for it in entities.user_mentions.iter() {
+        spans.push(Span {
+            start: it.indices[0],
+            end: it.indices[1],
+            link: Link::Mention(it.screen_name.clone()),
+        });
+    }
+    for it in entities.hashtags.iter() {
+        spans.push(Span {
+            start: it.indices[0],
+            end: it.indices[1],
+            link: Link::Hashtag(it.text.clone()),
+        });
+    }
+    for it in entities.symbols.iter() {
+        spans.push(Span {
+            start: it.indices[0],
+            end: it.indices[1],
+            link: Link::Cashtag(it.text.clone()),
+        });
+    }
+    for it in entities.urls.iter() {
+        spans.push(Span {
+            start: it.indices[0],
+            end: it.indices[1],
+            link: Link::Url {
+                href: it.expanded_url.clone().unwrap_or_else(|| it.url.clone()),
+                display: it.display_url.clone().unwrap_or_else(|| it.url.clone()),
+            },
+        });
+    }
+    for it in entities.media.iter() {
+        spans.push(Span {
+            start: it.indices[0],
+            end: it.indices[1],
+            link: Link::Media,
+        });
+    }
+    spans.sort_by_key(|it| it.start);
+    spans
+}
+
+// offsets[index] is the byte offset of index in unit, the last is text.len()
+fn byte_offsets(text: &str, unit: IndexUnit) -> Vec<usize> {
+    let mut offsets = vec![];
+    for (byte, c) in text.char_indices() {
+        let width = match unit {
+            IndexUnit::CodePoint => 1,
+            IndexUnit::Utf16 => c.len_utf16(),
+        };
+        for _ in 0..width {
+            offsets.push(byte);
+        }
+    }
+    offsets.push(text.len());
+    offsets
+}
+
+fn link(link: &Link, original: &str, format: Format) -> String {
+    let (href, display) = match link {
+        Link::Mention(screen_name) => (format!("https://twitter.com/{}", screen_name), original),
+        Link::Hashtag(text) => (
+            format!("https://twitter.com/hashtag/{}", encode(text)),
+            original,
+        ),
+        Link::Cashtag(text) => (
+            format!("https://twitter.com/search?q=%24{}", encode(text)),
+            original,
+        ),
+        Link::Url { href, display } => (href.clone(), display.as_str()),
+        Link::Media => return String::new(),
+    };
+    match format {
+        Format::Html => format!(
+            "<a href=\"{}\">{}</a>",
+            escape_html(&href),
+            escape_html(display)
+        ),
+        Format::Markdown => format!("[{}]({})", escape_markdown(display), markdown_url(&href)),
+    }
+}
+
+fn render_media(media: &Media, format: Format) -> String {
+    let alt = media.ext_alt_text.as_deref().unwrap_or_default();
+    let video = media
+        .video_info
+        .as_ref()
+        .and_then(|it| {
+            it.variants
+                .iter()
+                .filter(|it| it.content_type == "video/mp4")
+                .max_by_key(|it| it.bitrate.unwrap_or_default())
+        })
+        .map(|it| it.url.as_str());
+    match (format, video) {
+        (Format::Html, Some(video)) => format!(
+            "<video src=\"{}\" poster=\"{}\" controls></video>",
+            escape_html(video),
+            escape_html(&media.media_url_https)
+        ),
+        (Format::Html, None) => format!(
+            "<img src=\"{}\" alt=\"{}\">",
+            escape_html(&media.media_url_https),
+            escape_html(alt)
+        ),
+        (Format::Markdown, Some(video)) => format!(
+            "[![{}]({})]({})",
+            escape_markdown(alt),
+            markdown_url(&media.media_url_https),
+            markdown_url(video)
+        ),
+        (Format::Markdown, None) => {
+            format!(
+                "![{}]({})",
+                escape_markdown(alt),
+                markdown_url(&media.media_url_https)
+            )
+        }
+    }
+}
+
+fn plain(text: &str, format: Format) -> String {
+    match format {
+        Format::Html => escape_html(text),
+        Format::Markdown => escape_markdown(text),
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape_html(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\`*_[]<>".contains(c) {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+
+// Parentheses would close the link destination
+fn markdown_url(url: &str) -> String {
+    url.replace('(', "%28")
+        .replace(')', "%29")
+        .replace(' ', "%20")
+}
+
+fn encode(text: &str) -> String {
+    url::form_urlencoded::byte_serialize(text.as_bytes()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn html(json: serde_json::Value) -> String {
+        render_value(&json, Format::Html, &RenderOptions::default()).unwrap()
+    }
+
+    fn markdown(json: serde_json::Value) -> String {
+        render_value(&json, Format::Markdown, &RenderOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn applies_indices_to_unescaped_text() {
+        let tweet = json!({
+            "full_text": "a &amp; #b &lt;c&gt;",
+            "entities": {"hashtags": [{"text": "b", "indices": [4, 6]}]}
+        });
+        assert_eq!(
+            html(tweet.clone()),
+            "a &amp; <a href=\"https://twitter.com/hashtag/b\">#b</a> &lt;c&gt;"
+        );
+        assert_eq!(
+            markdown(tweet),
+            "a & [#b](https://twitter.com/hashtag/b) \\<c\\>"
+        );
+    }
+
+    #[test]
+    fn escapes_unescaped_text_for_html() {
+        let tweet = json!({
+            "text": "<script>x</script> #b",
+            "entities": {"hashtags": [{"text": "b", "indices": [19, 21]}]}
+        });
+        assert_eq!(
+            html(tweet),
+            "&lt;script&gt;x&lt;/script&gt; <a href=\"https://twitter.com/hashtag/b\">#b</a>"
+        );
+    }
+
+    #[test]
+    fn escapes_url_display() {
+        let tweet = json!({
+            "full_text": "see https://t.co/x",
+            "entities": {"urls": [{
+                "url": "https://t.co/x",
+                "expanded_url": "https://example.com/?a=1&b=\"2\"",
+                "display_url": "example.com/<b>",
+                "indices": [4, 18]
+            }]}
+        });
+        assert_eq!(
+            html(tweet),
+            "see <a href=\"https://example.com/?a=1&amp;b=&quot;2&quot;\">example.com/&lt;b&gt;</a>"
+        );
+    }
+
+    #[test]
+    fn counts_surrogate_pairs_in_utf16() {
+        let tweet = json!({
+            "full_text": "😷 @user 😷 #tag",
+            "entities": {
+                "user_mentions": [{"screen_name": "user", "indices": [3, 8]}],
+                "hashtags": [{"text": "tag", "indices": [12, 16]}]
+            }
+        });
+        let options = RenderOptions {
+            index_unit: IndexUnit::Utf16,
+            ..Default::default()
+        };
+        assert_eq!(
+            render_value(&tweet, Format::Markdown, &options).unwrap(),
+            "😷 [@user](https://twitter.com/user) 😷 [#tag](https://twitter.com/hashtag/tag)"
+        );
+        // the same indices are wrong in code points
+        let tweet = json!({
+            "full_text": "😷 @user 😷 #tag",
+            "entities": {
+                "user_mentions": [{"screen_name": "user", "indices": [2, 7]}],
+                "hashtags": [{"text": "tag", "indices": [10, 14]}]
+            }
+        });
+        assert_eq!(
+            markdown(tweet),
+            "😷 [@user](https://twitter.com/user) 😷 [#tag](https://twitter.com/hashtag/tag)"
+        );
+    }
+
+    #[test]
+    fn removes_media_url_and_appends_media() {
+        let media = json!({
+            "media_url_https": "https://pbs.twimg.com/media/a.jpg",
+            "ext_alt_text": "a \"cat\"",
+            "indices": [6, 29]
+        });
+        let tweet = json!({
+            "full_text": "photo https://t.co/abcdefghij",
+            "entities": {"media": [media]},
+            "extended_entities": {"media": [media]}
+        });
+        assert_eq!(
+            html(tweet.clone()),
+            "photo\n<img src=\"https://pbs.twimg.com/media/a.jpg\" alt=\"a &quot;cat&quot;\">"
+        );
+        let options = RenderOptions {
+            include_media: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            render_value(&tweet, Format::Html, &options).unwrap(),
+            "photo"
+        );
+    }
+
+    #[test]
+    fn hides_reply_mentions_by_display_text_range() {
+        let tweet = json!({
+            "full_text": "@user hi &amp; bye",
+            "display_text_range": [6, 14],
+            "entities": {"user_mentions": [{"screen_name": "user", "indices": [0, 5]}]}
+        });
+        assert_eq!(html(tweet), "hi &amp; bye");
+    }
+
+    #[test]
+    fn ignores_inverted_display_text_range() {
+        let tweet = json!({
+            "full_text": "@user hi",
+            "display_text_range": [8, 6],
+            "entities": {"user_mentions": [{"screen_name": "user", "indices": [0, 5]}]}
+        });
+        assert_eq!(
+            html(tweet),
+            "<a href=\"https://twitter.com/user\">@user</a> hi"
+        );
+    }
+
+    #[test]
+    fn renders_display_text_range_after_every_entity() {
+        let tweet = json!({
+            "full_text": "@user hi",
+            "display_text_range": [20, 30],
+            "entities": {"user_mentions": [{"screen_name": "user", "indices": [0, 5]}]}
+        });
+        assert_eq!(html(tweet), "");
+        let tweet = json!({
+            "full_text": "@user hi #a",
+            "display_text_range": [6, 8],
+            "entities": {
+                "user_mentions": [{"screen_name": "user", "indices": [0, 5]}],
+                "hashtags": [{"text": "a", "indices": [9, 11]}]
+            }
+        });
+        assert_eq!(html(tweet), "hi");
+    }
+}
@@ -0,0 +1,339 @@
+//! Rate limit aware retry for any Twapi implementation
+use super::{RateLimit, TResult, Twapi};
+use async_trait::async_trait;
+use rand::Rng;
+use std::{future::Future, time::Duration};
+use twapi_reqwest::reqwest::{multipart::Form, Error, Response};
+
+/// How RetryTwapi retries requests
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retry count after the first request
+    pub max_retries: u32,
+    /// First backoff delay, doubled on every retry
+    pub base_delay: Duration,
+    /// Upper bound of backoff delay
+    pub max_delay: Duration,
+    /// Randomize backoff delay between half and full of it
+    pub jitter: bool,
+    /// Sleep until x-rate-limit-reset on 429
+    pub wait_rate_limit: bool,
+    /// Give up on 429 when the reset is further than this
+    pub max_rate_limit_wait: Duration,
+    /// Also retry POST and JSON requests on 5xx and request errors.
+    /// They may have been processed already, e.g. a direct message is sent twice.
+    pub retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            jitter: true,
+            wait_rate_limit: true,
+            max_rate_limit_wait: Duration::from_secs(15 * 60),
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff delay of the attempt(0 origin)
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .checked_mul(2u32.saturating_pow(attempt))
+            .unwrap_or(self.max_delay)
+            .min(self.max_delay);
+        if self.jitter && delay > Duration::from_millis(0) {
+            let half = delay / 2;
+            half + rand::thread_rng().gen_range(Duration::from_millis(0)..=half)
+        } else {
+            delay
+        }
+    }
+
+    // 429 is rejected before processing, so it is retried for any method
+    fn delay_for_response(
+        &self,
+        response: &Response,
+        attempt: u32,
+        idempotent: bool,
+    ) -> Option<Duration> {
+        let status_code = response.status().as_u16();
+        if status_code == 429 {
+            if !self.wait_rate_limit {
+                return None;
+            }
+            let wait = match RateLimit::from_headers(response.headers()) {
+                // reset is second precision, wait one more second
+                Some(rate_limit) => rate_limit.reset_after() + Duration::from_secs(1),
+                None => self.backoff(attempt),
+            };
+            if wait > self.max_rate_limit_wait {
+                None
+            } else {
+                Some(wait)
+            }
+        } else if response.status().is_server_error() && self.retries(idempotent) {
+            Some(self.backoff(attempt))
+        } else {
+            None
+        }
+    }
+
+    fn delay_for_error(&self, err: &Error, attempt: u32, idempotent: bool) -> Option<Duration> {
+        if !self.retries(idempotent) {
+            None
+        } else if err.is_connect() || err.is_timeout() || err.is_request() {
+            Some(self.backoff(attempt))
+        } else {
+            None
+        }
+    }
+
+    fn retries(&self, idempotent: bool) -> bool {
+        idempotent || self.retry_non_idempotent
+    }
+}
+
+/// Wrap Twapi and retry 429, 5xx and connection errors
+///
+/// POST and JSON requests are retried only on 429 unless RetryPolicy::retry_non_idempotent.
+/// Multipart requests are not retried because the form can not be rebuilt.
+/// Waiting uses tokio::time, so it can be tested with a paused clock.
+pub struct RetryTwapi<T> {
+    inner: T,
+    policy: RetryPolicy,
+}
+
+impl<T: Twapi + Sync + Send> RetryTwapi<T> {
+    pub fn new(inner: T) -> RetryTwapi<T> {
+        RetryTwapi::with_policy(inner, RetryPolicy::default())
+    }
+
+    pub fn with_policy(inner: T, policy: RetryPolicy) -> RetryTwapi<T> {
+        RetryTwapi { inner, policy }
+    }
+
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    async fn execute<F, Fut>(&self, idempotent: bool, request: F) -> TResult
+    where
+        F: Fn() -> Fut + Send + Sync,
+        Fut: Future<Output = TResult> + Send,
+    {
+        let mut attempt = 0;
+        loop {
+            let result = request().await;
+            if attempt >= self.policy.max_retries {
+                return result;
+            }
+            let delay = match &result {
+                Ok(response) => self
+                    .policy
+                    .delay_for_response(response, attempt, idempotent),
+                Err(err) => self.policy.delay_for_error(err, attempt, idempotent),
+            };
+            match delay {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return result,
+            }
+            attempt += 1;
+        }
+    }
+}
+
+#[async_trait]
+impl<T: Twapi + Sync + Send> Twapi for RetryTwapi<T> {
+    async fn get(&self, uri: &str, query_options: &Vec<(&str, &str)>) -> TResult {
+        self.execute(true, || self.inner.get(uri, query_options))
+            .await
+    }
+
+    async fn post(
+        &self,
+        uri: &str,
+        query_options: &Vec<(&str, &str)>,
+        form_options: &Vec<(&str, &str)>,
+    ) -> TResult {
+        self.execute(false, || self.inner.post(uri, query_options, form_options))
+            .await
+    }
+
+    async fn multipart(&self, uri: &str, query_options: &Vec<(&str, &str)>, form: Form) -> TResult {
+        self.inner.multipart(uri, query_options, form).await
+    }
+
+    async fn put(&self, uri: &str, query_options: &Vec<(&str, &str)>) -> TResult {
+        self.execute(true, || self.inner.put(uri, query_options))
+            .await
+    }
+
+    async fn delete(&self, uri: &str, query_options: &Vec<(&str, &str)>) -> TResult {
+        self.execute(true, || self.inner.delete(uri, query_options))
+            .await
+    }
+
+    async fn json(
+        &self,
+        uri: &str,
+        query_options: &Vec<(&str, &str)>,
+        json: &serde_json::Value,
+    ) -> TResult {
+        self.execute(false, || self.inner.json(uri, query_options, json))
+            .await
+    }
+
+    async fn put_json(
+        &self,
+        uri: &str,
+        query_options: &Vec<(&str, &str)>,
+        json: &serde_json::Value,
+    ) -> TResult {
+        self.execute(true, || self.inner.put_json(uri, query_options, json))
+            .await
+    }
+
+    fn api_base_url(&self) -> &str {
+        self.inner.api_base_url()
+    }
+
+    fn upload_base_url(&self) -> &str {
+        self.inner.upload_base_url()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockResponse, MockTwapi};
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use tokio::time::Instant;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            jitter: false,
+            ..Default::default()
+        }
+    }
+
+    fn status(status_code: u16) -> MockResponse {
+        MockResponse::new(status_code, serde_json::json!({}))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_until_rate_limit_reset() {
+        let reset = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 10;
+        let twapi = RetryTwapi::with_policy(MockTwapi::new(), policy());
+        twapi.inner().push(
+            status(429)
+                .header("x-rate-limit-limit", "15")
+                .header("x-rate-limit-remaining", "0")
+                .header("x-rate-limit-reset", &reset.to_string()),
+        );
+        let start = Instant::now();
+        let response = twapi
+            .get("https://api.twitter.com/a", &vec![])
+            .await
+            .unwrap();
+        let elapsed = start.elapsed();
+        assert_eq!(response.status().as_u16(), 200);
+        assert!(elapsed >= Duration::from_secs(10), "{:?}", elapsed);
+        assert!(elapsed <= Duration::from_secs(12), "{:?}", elapsed);
+        assert_eq!(twapi.inner().requests().len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backs_off_on_server_error() {
+        let twapi = RetryTwapi::with_policy(MockTwapi::new(), policy());
+        twapi.inner().push(status(500)).push(status(503));
+        let start = Instant::now();
+        let response = twapi
+            .get("https://api.twitter.com/a", &vec![])
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        // 1s then 2s
+        assert_eq!(start.elapsed(), Duration::from_secs(3));
+        assert_eq!(twapi.inner().requests().len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_after_max_retries() {
+        let twapi = RetryTwapi::with_policy(
+            MockTwapi::new(),
+            RetryPolicy {
+                max_retries: 2,
+                ..policy()
+            },
+        );
+        for _ in 0..4 {
+            twapi.inner().push(status(503));
+        }
+        let response = twapi
+            .delete("https://api.twitter.com/a", &vec![])
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 503);
+        assert_eq!(twapi.inner().requests().len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn does_not_retry_post_on_server_error() {
+        let twapi = RetryTwapi::with_policy(MockTwapi::new(), policy());
+        twapi.inner().push(status(500));
+        let response = twapi
+            .json("https://api.twitter.com/a", &vec![], &serde_json::json!({}))
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 500);
+        assert_eq!(twapi.inner().requests().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_post_on_rate_limit() {
+        let twapi = RetryTwapi::with_policy(MockTwapi::new(), policy());
+        twapi.inner().push(status(429));
+        let response = twapi
+            .post("https://api.twitter.com/a", &vec![], &vec![])
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(twapi.inner().requests().len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_post_when_allowed() {
+        let twapi = RetryTwapi::with_policy(
+            MockTwapi::new(),
+            RetryPolicy {
+                retry_non_idempotent: true,
+                ..policy()
+            },
+        );
+        twapi.inner().push(status(500));
+        let response = twapi
+            .post("https://api.twitter.com/a", &vec![], &vec![])
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(twapi.inner().requests().len(), 2);
+    }
+}
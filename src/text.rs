@@ -0,0 +1,811 @@
+//! Tweet text length and entities by twitter-text rules (config v3)
+//!
+//! Text is not NFC normalized and emoji are detected by code point ranges,
+//! so the result may differ from twitter-text on unusual input.
+use std::ops::Range;
+
+/// Maximum weighted length of a tweet
+pub const MAX_WEIGHTED_LENGTH: usize = 280;
+
+/// Weighted length of any URL
+pub const TRANSFORMED_URL_LENGTH: usize = 23;
+
+/// Smallest max_length of split, the weighted length of one wide character
+pub const MIN_SPLIT_LENGTH: usize = 2;
+
+const SCALE: usize = 100;
+const DEFAULT_WEIGHT: usize = 200;
+const LIGHT_WEIGHT: usize = 100;
+const LIGHT_RANGES: [(u32, u32); 4] = [
+    (0x0000, 0x10FF),
+    (0x2000, 0x200D),
+    (0x2010, 0x201F),
+    (0x2032, 0x2037),
+];
+
+// TLDs accepted without scheme, other TLDs need a path
+const GENERIC_TLDS: [&str; 18] = [
+    "com", "net", "org", "edu", "gov", "mil", "int", "info", "biz", "name", "app", "dev", "xyz",
+    "online", "site", "tech", "blog", "news",
+];
+
+/// Length of text as counted by Twitter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TweetLength {
+    pub weighted_length: usize,
+    /// weighted_length per MAX_WEIGHTED_LENGTH in 1/1000
+    pub permillage: usize,
+    /// Not empty, not too long and no invalid characters
+    pub is_valid: bool,
+}
+
+/// Kind of TextEntity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntityKind {
+    Url,
+    Mention,
+    Hashtag,
+    Cashtag,
+}
+
+/// Entity in text, range is byte offsets of text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEntity {
+    pub kind: EntityKind,
+    pub range: Range<usize>,
+    /// Without @, # or $ for mention, hashtag and cashtag
+    pub value: String,
+}
+
+pub fn parse_tweet(text: &str) -> TweetLength {
+    let weighted_length = weighted_length(text);
+    let has_invalid = text
+        .chars()
+        .any(|c| matches!(c, '\u{FFFE}' | '\u{FEFF}' | '\u{FFFF}'));
+    TweetLength {
+        weighted_length,
+        permillage: weighted_length * 1000 / MAX_WEIGHTED_LENGTH,
+        is_valid: !text.trim().is_empty() && weighted_length <= MAX_WEIGHTED_LENGTH && !has_invalid,
+    }
+}
+
+/// URLs count TRANSFORMED_URL_LENGTH, emoji and characters out of light ranges count 2
+pub fn weighted_length(text: &str) -> usize {
+    let urls = extract_urls(text);
+    let mut weight = 0;
+    let mut url_iter = urls.iter().peekable();
+    let mut chars = text.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        if let Some(url) = url_iter.peek() {
+            if url.range.start == index {
+                weight += TRANSFORMED_URL_LENGTH * SCALE;
+                while matches!(chars.peek(), Some((i, _)) if *i < url.range.end) {
+                    chars.next();
+                }
+                url_iter.next();
+                continue;
+            }
+        }
+        if is_emoji_start(c, chars.peek().map(|(_, it)| *it)) {
+            skip_emoji_sequence(c, &mut chars);
+            weight += DEFAULT_WEIGHT;
+        } else {
+            weight += char_weight(c);
+        }
+    }
+    weight / SCALE
+}
+
+pub fn is_valid_tweet(text: &str) -> bool {
+    parse_tweet(text).is_valid
+}
+
+fn char_weight(c: char) -> usize {
+    let code = c as u32;
+    if LIGHT_RANGES
+        .iter()
+        .any(|(start, end)| *start <= code && code <= *end)
+    {
+        LIGHT_WEIGHT
+    } else {
+        DEFAULT_WEIGHT
+    }
+}
+
+fn is_emoji_start(c: char, next: Option<char>) -> bool {
+    match c as u32 {
+        0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2B00..=0x2BFF | 0x231A..=0x23FF => true,
+        _ if c.is_ascii_digit() || c == '#' || c == '*' => {
+            matches!(next, Some('\u{FE0F}') | Some('\u{20E3}'))
+        }
+        _ => false,
+    }
+}
+
+// Modifiers, ZWJ sequences and regional indicator pairs belong to one emoji
+fn skip_emoji_sequence<I>(first: char, chars: &mut std::iter::Peekable<I>)
+where
+    I: Iterator<Item = (usize, char)>,
+{
+    let is_regional = |c: char| (0x1F1E6..=0x1F1FF).contains(&(c as u32));
+    if is_regional(first) {
+        if matches!(chars.peek(), Some((_, c)) if is_regional(*c)) {
+            chars.next();
+        }
+        return;
+    }
+    while let Some((_, c)) = chars.peek() {
+        match *c as u32 {
+            0xFE0E | 0xFE0F | 0x20E3 | 0x1F3FB..=0x1F3FF | 0xE0020..=0xE007F => {
+                chars.next();
+            }
+            0x200D => {
+                chars.next();
+                chars.next();
+            }
+            _ => break,
+        }
+    }
+}
+
+/// URLs, mentions, hashtags and cashtags ordered by position.
+/// Mentions, hashtags and cashtags inside URLs are removed.
+pub fn extract_entities(text: &str) -> Vec<TextEntity> {
+    let urls = extract_urls(text);
+    let in_url = |entity: &TextEntity| {
+        urls.iter()
+            .any(|url| url.range.start < entity.range.end && entity.range.start < url.range.end)
+    };
+    let mut entities: Vec<TextEntity> = extract_mentions(text)
+        .into_iter()
+        .chain(extract_hashtags(text))
+        .chain(extract_cashtags(text))
+        .filter(|it| !in_url(it))
+        .chain(urls.iter().cloned())
+        .collect();
+    entities.sort_by_key(|it| it.range.start);
+    entities
+}
+
+/// URLs with http or https scheme, or domains of known TLD
+pub fn extract_urls(text: &str) -> Vec<TextEntity> {
+    let mut urls = vec![];
+    let bytes = text.as_bytes();
+    let mut start = 0;
+    while start < bytes.len() {
+        if !is_url_byte(bytes[start]) {
+            start += 1;
+            continue;
+        }
+        let mut end = start;
+        while end < bytes.len() && is_url_byte(bytes[end]) {
+            end += 1;
+        }
+        if let Some(range) = find_url(text, start..end) {
+            urls.push(TextEntity {
+                kind: EntityKind::Url,
+                value: String::from(&text[range.clone()]),
+                range,
+            });
+        }
+        start = end;
+    }
+    urls
+}
+
+fn is_url_byte(b: u8) -> bool {
+    b.is_ascii_graphic() && !matches!(b, b'<' | b'>' | b'"' | b'`' | b'{' | b'}' | b'|' | b'\\')
+}
+
+fn find_url(text: &str, run: Range<usize>) -> Option<Range<usize>> {
+    let lower = text[run.clone()].to_ascii_lowercase();
+    let scheme = ["https://", "http://"]
+        .iter()
+        .filter_map(|scheme| lower.find(scheme))
+        .min();
+    let start = match scheme {
+        Some(offset) => {
+            let start = run.start + offset;
+            if offset > 0 {
+                let before = text.as_bytes()[start - 1];
+                if before.is_ascii_alphanumeric() || matches!(before, b'@' | b'$' | b'#') {
+                    return None;
+                }
+            }
+            start
+        }
+        None => {
+            let offset = lower.find(|c: char| c.is_ascii_alphanumeric())?;
+            let before = text[..run.start + offset].chars().last();
+            if matches!(before, Some(c) if c == '@' || c == '$' || c == '#' || c == '.' || c == '-')
+            {
+                return None;
+            }
+            run.start + offset
+        }
+    };
+    let host_start = match scheme {
+        Some(_) => start + text[start..].find("://")? + 3,
+        None => start,
+    };
+    let host_end = text[host_start..run.end]
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '-'))
+        .map(|it| host_start + it)
+        .unwrap_or(run.end);
+    let host = text[host_start..host_end].trim_end_matches(['.', '-']);
+    let host_end = host_start + host.len();
+    let labels: Vec<&str> = host.split('.').collect();
+    if labels.len() < 2 || labels.iter().any(|it| it.is_empty()) {
+        return None;
+    }
+    let tld = labels[labels.len() - 1].to_ascii_lowercase();
+    if !tld.chars().all(|c| c.is_ascii_alphabetic()) || tld.len() < 2 {
+        return None;
+    }
+    let has_path = text[host_end..run.end].starts_with('/');
+    if scheme.is_none() && !GENERIC_TLDS.contains(&tld.as_str()) && !(tld.len() == 2 && has_path) {
+        return None;
+    }
+    let end = if text[host_end..run.end].starts_with(['/', ':', '?']) {
+        trim_url_end(text, host_end, run.end)
+    } else {
+        host_end
+    };
+    Some(start..end)
+}
+
+// Trailing punctuation and unbalanced brackets are not part of URL
+fn trim_url_end(text: &str, start: usize, mut end: usize) -> usize {
+    loop {
+        let url = &text[start..end];
+        let last = match url.chars().last() {
+            Some(last) => last,
+            None => return end,
+        };
+        let unbalanced = |open: char, close: char| {
+            last == close && url.matches(open).count() < url.matches(close).count()
+        };
+        if ".,;:!?'*".contains(last) || unbalanced('(', ')') || unbalanced('[', ']') {
+            end -= 1;
+        } else {
+            return end;
+        }
+    }
+}
+
+/// @screen_name, up to 20 characters
+pub fn extract_mentions(text: &str) -> Vec<TextEntity> {
+    // CJK may precede a mention without space
+    let blocks = |b: char| b.is_ascii_alphanumeric() || "_!#$%&*@＠".contains(b);
+    extract_prefixed(text, &['@', '＠'], EntityKind::Mention, blocks, |name| {
+        let length = name
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(name.len())
+            .min(20);
+        let rest = &name[length..];
+        if length == 0 || rest.starts_with(['@', '＠']) || rest.starts_with("://") {
+            None
+        } else {
+            Some(length)
+        }
+    })
+}
+
+/// #hashtag of letters, digits and underscore, not only digits
+pub fn extract_hashtags(text: &str) -> Vec<TextEntity> {
+    extract_prefixed(text, &['#', '＃'], EntityKind::Hashtag, blocks_tag, |tag| {
+        let length = tag.find(|c: char| !is_hashtag_char(c)).unwrap_or(tag.len());
+        let rest = &tag[length..];
+        if length == 0
+            || tag[..length].chars().all(|c| c.is_numeric() || c == '_')
+            || rest.starts_with(['#', '＃'])
+            || rest.starts_with("://")
+        {
+            None
+        } else {
+            Some(length)
+        }
+    })
+}
+
+/// $cashtag of 1 to 6 letters
+pub fn extract_cashtags(text: &str) -> Vec<TextEntity> {
+    extract_prefixed(text, &['$'], EntityKind::Cashtag, blocks_tag, |tag| {
+        let mut length = tag
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(tag.len());
+        if length == 0 || length > 6 {
+            return None;
+        }
+        // $BRK.A or $BRK_B
+        let rest = &tag.as_bytes()[length..];
+        if rest.len() >= 2 && matches!(rest[0], b'.' | b'_') && rest[1].is_ascii_alphabetic() {
+            let suffix = rest[1..]
+                .iter()
+                .take_while(|it| it.is_ascii_alphabetic())
+                .count();
+            if suffix <= 2 {
+                length += 1 + suffix;
+            }
+        }
+        match tag[length..].chars().next() {
+            Some(c) if c.is_alphanumeric() || c == '_' => None,
+            _ => Some(length),
+        }
+    })
+}
+
+fn is_hashtag_char(c: char) -> bool {
+    c.is_alphanumeric()
+        || c == '_'
+        || matches!(
+            c,
+            '\u{200C}' | '\u{200D}' | '\u{A67E}' | '\u{30FB}' | '\u{00B7}'
+        )
+}
+
+fn blocks_tag(b: char) -> bool {
+    b.is_alphanumeric() || "_!#$%&*@＠＃".contains(b)
+}
+
+// blocks is true for a preceding char that is not a boundary,
+// measure returns byte length of the body after the prefix
+fn extract_prefixed<B, F>(
+    text: &str,
+    prefixes: &[char],
+    kind: EntityKind,
+    blocks: B,
+    measure: F,
+) -> Vec<TextEntity>
+where
+    B: Fn(char) -> bool,
+    F: Fn(&str) -> Option<usize>,
+{
+    let mut entities = vec![];
+    let mut before: Option<char> = None;
+    let mut index = 0;
+    while let Some(c) = text[index..].chars().next() {
+        let is_boundary = !matches!(before, Some(b) if blocks(b));
+        let body_start = index + c.len_utf8();
+        let length = if prefixes.contains(&c) && is_boundary {
+            measure(&text[body_start..])
+        } else {
+            None
+        };
+        match length {
+            Some(length) => {
+                let end = body_start + length;
+                entities.push(TextEntity {
+                    kind,
+                    range: index..end,
+                    value: String::from(&text[body_start..end]),
+                });
+                before = text[..end].chars().last();
+                index = end;
+            }
+            None => {
+                before = Some(c);
+                index = body_start;
+            }
+        }
+    }
+    entities
+}
+
+/// Split text into valid tweets of up to max_length weighted length.
+/// Breaks at sentence ends, then at whitespace, and between clusters as a last resort.
+/// max_length below MIN_SPLIT_LENGTH is raised to it, so no part exceeds the limit
+/// except a URL that is never split and is a part alone when max_length is below
+/// TRANSFORMED_URL_LENGTH.
+pub fn split(text: &str, max_length: usize) -> Vec<String> {
+    let max_length = max_length.max(MIN_SPLIT_LENGTH);
+    let mut parts = vec![];
+    let mut current = String::new();
+    let fits = |it: &str| weighted_length(it.trim()) <= max_length;
+    for sentence in sentences(text) {
+        if fits(&format!("{}{}", current, sentence)) {
+            current.push_str(sentence);
+            continue;
+        }
+        push_part(&mut parts, &mut current);
+        if fits(sentence) {
+            current.push_str(sentence);
+            continue;
+        }
+        for word in words(sentence) {
+            if fits(&format!("{}{}", current, word)) {
+                current.push_str(word);
+                continue;
+            }
+            push_part(&mut parts, &mut current);
+            if fits(word) {
+                current.push_str(word);
+                continue;
+            }
+            for cluster in clusters(word) {
+                if !fits(&format!("{}{}", current, cluster)) {
+                    push_part(&mut parts, &mut current);
+                }
+                current.push_str(cluster);
+            }
+        }
+    }
+    push_part(&mut parts, &mut current);
+    parts
+}
+
+fn push_part(parts: &mut Vec<String>, current: &mut String) {
+    let part = current.trim();
+    if !part.is_empty() {
+        parts.push(String::from(part));
+    }
+    current.clear();
+}
+
+// URLs, emoji sequences and characters with following combining marks,
+// variation selectors and ZWJ joined characters, approximating grapheme clusters
+fn clusters(text: &str) -> Vec<&str> {
+    let urls = extract_urls(text);
+    let mut result = vec![];
+    let mut url_iter = urls.iter().peekable();
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if let Some(url) = url_iter.peek() {
+            if url.range.start == start {
+                while matches!(chars.peek(), Some((i, _)) if *i < url.range.end) {
+                    chars.next();
+                }
+                result.push(&text[url.range.clone()]);
+                url_iter.next();
+                continue;
+            }
+        }
+        if is_emoji_start(c, chars.peek().map(|(_, it)| *it)) {
+            skip_emoji_sequence(c, &mut chars);
+        }
+        while let Some((_, c)) = chars.peek() {
+            match *c as u32 {
+                0x200D => {
+                    chars.next();
+                    chars.next();
+                }
+                0x0300..=0x036F
+                | 0x1AB0..=0x1AFF
+                | 0x1DC0..=0x1DFF
+                | 0x20D0..=0x20FF
+                | 0x3099..=0x309A
+                | 0xFE00..=0xFE0F
+                | 0xFE20..=0xFE2F
+                | 0xE0100..=0xE01EF => {
+                    chars.next();
+                }
+                _ => break,
+            }
+        }
+        let end = chars.peek().map_or(text.len(), |(i, _)| *i);
+        result.push(&text[start..end]);
+    }
+    result
+}
+
+// Each sentence keeps its terminator and following whitespace
+fn sentences(text: &str) -> Vec<&str> {
+    split_after(text, |prev, c| {
+        prev == '\n'
+            || ("。！？".contains(prev) && !c.is_whitespace())
+            || (".!?".contains(prev) && c.is_whitespace())
+    })
+}
+
+// Each word keeps its following whitespace
+fn words(text: &str) -> Vec<&str> {
+    split_after(text, |prev, c| prev.is_whitespace() && !c.is_whitespace())
+}
+
+// Split before c when is_break(previous char, c)
+fn split_after<F: Fn(char, char) -> bool>(text: &str, is_break: F) -> Vec<&str> {
+    let mut result = vec![];
+    let mut start = 0;
+    let mut prev: Option<char> = None;
+    let mut pending = false;
+    for (index, c) in text.char_indices() {
+        if let Some(p) = prev {
+            // terminator is followed by whitespace, break after the whitespace
+            if is_break(p, c) {
+                pending = true;
+            }
+            if pending && !c.is_whitespace() {
+                result.push(&text[start..index]);
+                start = index;
+                pending = false;
+            }
+        }
+        prev = Some(c);
+    }
+    if start < text.len() {
+        result.push(&text[start..]);
+    }
+    result
+}
+
+// Cases adapted from twitter-text conformance validate.yml and extract.yml
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(entities: Vec<TextEntity>) -> Vec<String> {
+        entities.into_iter().map(|it| it.value).collect()
+    }
+
+    #[test]
+    fn counts_latin_as_one() {
+        assert_eq!(weighted_length("This is a test."), 15);
+        assert_eq!(weighted_length("café naïve"), 10);
+        assert!(is_valid_tweet(&"a".repeat(280)));
+        assert!(!is_valid_tweet(&"a".repeat(281)));
+    }
+
+    #[test]
+    fn counts_cjk_as_two() {
+        assert_eq!(weighted_length("こんにちは"), 10);
+        assert_eq!(weighted_length("日本語 text"), 11);
+        assert_eq!(weighted_length("한국어"), 6);
+        assert!(is_valid_tweet(&"あ".repeat(140)));
+        assert!(!is_valid_tweet(&"あ".repeat(141)));
+    }
+
+    #[test]
+    fn counts_emoji_as_two() {
+        assert_eq!(weighted_length("😷"), 2);
+        assert_eq!(weighted_length("❤️"), 2);
+        // skin tone modifier
+        assert_eq!(weighted_length("👍🏽"), 2);
+        // ZWJ sequences
+        assert_eq!(weighted_length("👨‍👩‍👧‍👦"), 2);
+        assert_eq!(weighted_length("🏳️‍🌈"), 2);
+        // flags
+        assert_eq!(weighted_length("🇯🇵"), 2);
+        assert_eq!(weighted_length("🇯🇵🇺🇸"), 4);
+        assert_eq!(weighted_length("🏴󠁧󠁢󠁳󠁣󠁴󠁿"), 2);
+        // keycaps
+        assert_eq!(weighted_length("1️⃣"), 2);
+        assert_eq!(weighted_length("#️⃣"), 2);
+        assert_eq!(weighted_length("Hello 😷 world"), 14);
+        assert_eq!(weighted_length(&"😷".repeat(140)), 280);
+    }
+
+    #[test]
+    fn counts_urls_as_transformed_length() {
+        assert_eq!(
+            weighted_length("https://example.com/a/very/long/path/that/is/longer/than/23"),
+            TRANSFORMED_URL_LENGTH
+        );
+        assert_eq!(weighted_length("http://t.co"), TRANSFORMED_URL_LENGTH);
+        assert_eq!(weighted_length("Check example.com!"), 30);
+        let text = format!("{} https://example.com", "a".repeat(256));
+        assert_eq!(weighted_length(&text), 280);
+        assert!(is_valid_tweet(&text));
+    }
+
+    #[test]
+    fn rejects_empty_and_invalid_characters() {
+        assert!(!is_valid_tweet(""));
+        assert!(!is_valid_tweet("  \n"));
+        assert!(!is_valid_tweet("a\u{FFFE}"));
+        assert!(!is_valid_tweet("a\u{FEFF}"));
+        let length = parse_tweet(&"a".repeat(140));
+        assert_eq!(length.weighted_length, 140);
+        assert_eq!(length.permillage, 500);
+    }
+
+    #[test]
+    fn extracts_urls_with_scheme() {
+        assert_eq!(
+            values(extract_urls("visit http://example.com now")),
+            vec!["http://example.com"]
+        );
+        assert_eq!(
+            values(extract_urls("HTTPS://EXAMPLE.COM/Path?a=1&b=2#frag")),
+            vec!["HTTPS://EXAMPLE.COM/Path?a=1&b=2#frag"]
+        );
+        assert_eq!(
+            values(extract_urls("https://example.museum")),
+            vec!["https://example.museum"]
+        );
+        assert_eq!(
+            values(extract_urls("abchttp://example.com")),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn extracts_urls_without_scheme() {
+        assert_eq!(values(extract_urls("see example.com")), vec!["example.com"]);
+        assert_eq!(
+            values(extract_urls("see www.example.org/path")),
+            vec!["www.example.org/path"]
+        );
+        // ccTLD needs a path without scheme
+        assert_eq!(
+            values(extract_urls("example.co.jp/foo and example.jp")),
+            vec!["example.co.jp/foo"]
+        );
+        assert_eq!(values(extract_urls("example.museum")), Vec::<String>::new());
+        // mailbox and mention like prefixes
+        assert_eq!(
+            values(extract_urls("foo@example.com")),
+            Vec::<String>::new()
+        );
+        assert_eq!(values(extract_urls("#example.com")), Vec::<String>::new());
+        assert_eq!(values(extract_urls("1.5")), Vec::<String>::new());
+    }
+
+    #[test]
+    fn trims_trailing_punctuation_of_urls() {
+        assert_eq!(
+            values(extract_urls("go to https://example.com/path.")),
+            vec!["https://example.com/path"]
+        );
+        assert_eq!(
+            values(extract_urls("https://example.com/a?b=c!?")),
+            vec!["https://example.com/a?b=c"]
+        );
+        assert_eq!(
+            values(extract_urls("example.com, example.net.")),
+            vec!["example.com", "example.net"]
+        );
+        assert_eq!(
+            values(extract_urls("(see https://example.com/a)")),
+            vec!["https://example.com/a"]
+        );
+        assert_eq!(
+            values(extract_urls(
+                "https://en.wikipedia.org/wiki/Rust_(programming_language)."
+            )),
+            vec!["https://en.wikipedia.org/wiki/Rust_(programming_language)"]
+        );
+    }
+
+    #[test]
+    fn extracts_mentions() {
+        assert_eq!(values(extract_mentions("@user hello")), vec!["user"]);
+        assert_eq!(
+            values(extract_mentions("hi @alice, @bob_2 and ＠carol")),
+            vec!["alice", "bob_2", "carol"]
+        );
+        assert_eq!(
+            values(extract_mentions("mail@example")),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            values(extract_mentions("@user@other")),
+            Vec::<String>::new()
+        );
+        assert_eq!(values(extract_mentions("@ user")), Vec::<String>::new());
+        assert_eq!(
+            values(extract_mentions("@aaaaaaaaaabbbbbbbbbbcc")),
+            vec!["aaaaaaaaaabbbbbbbbbb"]
+        );
+        let entities = extract_mentions("日本語@user");
+        assert_eq!(entities[0].range, 9..14);
+    }
+
+    #[test]
+    fn extracts_hashtags() {
+        assert_eq!(values(extract_hashtags("#hashtag text")), vec!["hashtag"]);
+        assert_eq!(
+            values(extract_hashtags("#日本語 ＃ハッシュタグ #hash_tag")),
+            vec!["日本語", "ハッシュタグ", "hash_tag"]
+        );
+        assert_eq!(values(extract_hashtags("#a123 #123 #_1")), vec!["a123"]);
+        assert_eq!(
+            values(extract_hashtags("text#hashtag")),
+            Vec::<String>::new()
+        );
+        assert_eq!(values(extract_hashtags("#hashtag.")), vec!["hashtag"]);
+        assert_eq!(values(extract_hashtags("#ab#cd")), Vec::<String>::new());
+    }
+
+    #[test]
+    fn extracts_cashtags() {
+        assert_eq!(values(extract_cashtags("buy $TWTR now")), vec!["TWTR"]);
+        assert_eq!(values(extract_cashtags("$twtr")), vec!["twtr"]);
+        assert_eq!(
+            values(extract_cashtags("$BRK.A and $BRK_B")),
+            vec!["BRK.A", "BRK_B"]
+        );
+        assert_eq!(
+            values(extract_cashtags("$1000 $TOOLONG")),
+            Vec::<String>::new()
+        );
+        assert_eq!(values(extract_cashtags("US$TWTR")), Vec::<String>::new());
+    }
+
+    #[test]
+    fn removes_entities_inside_urls() {
+        let entities = extract_entities("@user see https://example.com/#tag?$TWTR #real");
+        let kinds: Vec<_> = entities.iter().map(|it| it.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![EntityKind::Mention, EntityKind::Url, EntityKind::Hashtag]
+        );
+        assert_eq!(entities[2].value, "real");
+    }
+
+    #[test]
+    fn splits_at_sentences_then_words() {
+        assert_eq!(
+            split("First one. Second one. Third.", 12),
+            vec!["First one.", "Second one.", "Third."]
+        );
+        assert_eq!(split("aaa bbb ccc ddd", 8), vec!["aaa bbb", "ccc ddd"]);
+        assert_eq!(
+            split("これは文です。次の文。", 14),
+            vec!["これは文です。", "次の文。"]
+        );
+        assert_eq!(split("abcdefgh", 3), vec!["abc", "def", "gh"]);
+    }
+
+    #[test]
+    fn split_parts_fit_max_length() {
+        let text = "Emoji 😷😷😷 and 日本語 and https://example.com/path. Done!";
+        for max_length in 2..40 {
+            for part in split(text, max_length) {
+                let is_url = extract_urls(&part)
+                    .first()
+                    .is_some_and(|url| url.value == part);
+                assert!(
+                    weighted_length(&part) <= max_length || is_url,
+                    "{} {}",
+                    max_length,
+                    part
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn split_clamps_too_small_max_length() {
+        assert_eq!(split("xy", 0), vec!["xy"]);
+        assert_eq!(split("日本", 1), vec!["日", "本"]);
+        assert!(split("", 10).is_empty());
+    }
+
+    #[test]
+    fn split_keeps_clusters() {
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        assert_eq!(split(&family.repeat(3), 2), vec![family, family, family]);
+        assert_eq!(
+            split("\u{1F1EF}\u{1F1F5}\u{1F1FA}\u{1F1F8}", 3),
+            vec!["\u{1F1EF}\u{1F1F5}", "\u{1F1FA}\u{1F1F8}"]
+        );
+        assert_eq!(
+            split("\u{1F44D}\u{1F3FD}\u{1F44D}\u{1F3FD}", 2),
+            vec!["\u{1F44D}\u{1F3FD}", "\u{1F44D}\u{1F3FD}"]
+        );
+        // e and combining acute accent
+        assert_eq!(
+            split("e\u{301}e\u{301}e\u{301}", 3),
+            vec!["e\u{301}", "e\u{301}", "e\u{301}"]
+        );
+        // か and combining dakuten
+        assert_eq!(
+            split("か\u{3099}か\u{3099}", 3),
+            vec!["か\u{3099}", "か\u{3099}"]
+        );
+    }
+
+    #[test]
+    fn split_never_cuts_urls() {
+        let url = "https://example.com/a/very/long/path";
+        assert_eq!(
+            split(&format!("aaaaaaaaaa:{}", url), 25),
+            vec![String::from("aaaaaaaaaa:"), String::from(url)]
+        );
+        assert_eq!(
+            split(&format!("abc:{}!?!", url), 10),
+            vec!["abc:", url, "!?!"]
+        );
+        assert_eq!(split(&format!("{}.", url), 24), vec![format!("{}.", url)]);
+    }
+}
@@ -0,0 +1,835 @@
+//! Tweet composition with media, replies, quotes, polls and location,
+//! and threads of chained replies
+use super::{
+    media::{media_id_of, MediaUploader, MediaUsage},
+    media_metadata::MediaMetadata,
+    models::Tweet,
+    text,
+    v2::TwapiV2,
+    Twapi, TwapiError,
+};
+use serde_json::json;
+
+/// Maximum media of one tweet
+pub const MAX_MEDIA_COUNT: usize = 4;
+
+/// Poll options must be in this range
+pub const POLL_OPTIONS_RANGE: std::ops::RangeInclusive<usize> = 2..=4;
+
+/// Maximum characters of a poll option
+pub const MAX_POLL_OPTION_LENGTH: usize = 25;
+
+/// Poll duration must be in this range
+pub const POLL_DURATION_MINUTES_RANGE: std::ops::RangeInclusive<u32> = 5..=10080;
+
+/// Invalid combination of TweetBuilder
+#[derive(Debug, Clone, PartialEq)]
+pub enum TweetError {
+    /// No text, media nor attachment
+    Empty,
+    /// Weighted length of text exceeds text::MAX_WEIGHTED_LENGTH
+    TooLong(usize),
+    /// More than MAX_MEDIA_COUNT media
+    TooManyMedia(usize),
+    /// Only one of media, attachment_url and poll is allowed
+    ConflictingAttachments,
+    /// auto_populate_reply_metadata requires in_reply_to_status_id
+    ReplyMetadataWithoutReply,
+    /// Latitude or longitude out of range
+    InvalidCoordinates { lat: f64, long: f64 },
+    /// Option count, option length or duration out of range
+    InvalidPoll(String),
+    /// Coordinates can not be posted with poll
+    CoordinatesWithPoll,
+}
+
+impl From<TweetError> for TwapiError {
+    fn from(err: TweetError) -> TwapiError {
+        TwapiError::Tweet(err)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum MediaSource {
+    Id(String),
+    Path {
+        path: String,
+        alt_text: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Poll {
+    options: Vec<String>,
+    duration_minutes: u32,
+}
+
+/// Builder of statuses/update
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TweetBuilder {
+    text: String,
+    media: Vec<MediaSource>,
+    in_reply_to_status_id: Option<String>,
+    auto_populate_reply_metadata: bool,
+    exclude_reply_user_ids: Vec<String>,
+    attachment_url: Option<String>,
+    poll: Option<Poll>,
+    coordinates: Option<(f64, f64)>,
+    display_coordinates: bool,
+    place_id: Option<String>,
+    possibly_sensitive: bool,
+}
+
+impl TweetBuilder {
+    pub fn new(text: &str) -> TweetBuilder {
+        TweetBuilder {
+            text: String::from(text),
+            ..Default::default()
+        }
+    }
+
+    /// Already uploaded media
+    pub fn media_id(mut self, media_id: &str) -> TweetBuilder {
+        self.media.push(MediaSource::Id(String::from(media_id)));
+        self
+    }
+
+    /// Uploaded by MediaUploader before posting
+    pub fn media_path(mut self, path: &str) -> TweetBuilder {
+        self.media.push(MediaSource::Path {
+            path: String::from(path),
+            alt_text: None,
+        });
+        self
+    }
+
+    /// Uploaded by MediaUploader and described by media/metadata/create
+    pub fn media_path_with_alt_text(mut self, path: &str, alt_text: &str) -> TweetBuilder {
+        self.media.push(MediaSource::Path {
+            path: String::from(path),
+            alt_text: Some(String::from(alt_text)),
+        });
+        self
+    }
+
+    pub fn in_reply_to_status_id(mut self, id: &str) -> TweetBuilder {
+        self.in_reply_to_status_id = Some(String::from(id));
+        self
+    }
+
+    /// Mentions of the replied tweet are added by Twitter
+    pub fn auto_populate_reply_metadata(mut self, value: bool) -> TweetBuilder {
+        self.auto_populate_reply_metadata = value;
+        self
+    }
+
+    pub fn exclude_reply_user_id(mut self, user_id: &str) -> TweetBuilder {
+        self.exclude_reply_user_ids.push(String::from(user_id));
+        self
+    }
+
+    /// Tweet permalink or DM deep link, not counted in text
+    pub fn attachment_url(mut self, url: &str) -> TweetBuilder {
+        self.attachment_url = Some(String::from(url));
+        self
+    }
+
+    /// Quote tweet by id
+    pub fn quote(self, tweet_id: &str) -> TweetBuilder {
+        self.attachment_url(&format!("https://twitter.com/i/web/status/{}", tweet_id))
+    }
+
+    /// Posted by /2/tweets because statuses/update has no poll
+    pub fn poll(mut self, options: &[&str], duration_minutes: u32) -> TweetBuilder {
+        self.poll = Some(Poll {
+            options: options.iter().map(|it| String::from(*it)).collect(),
+            duration_minutes,
+        });
+        self
+    }
+
+    pub fn coordinates(mut self, lat: f64, long: f64) -> TweetBuilder {
+        self.coordinates = Some((lat, long));
+        self
+    }
+
+    pub fn display_coordinates(mut self, value: bool) -> TweetBuilder {
+        self.display_coordinates = value;
+        self
+    }
+
+    pub fn place_id(mut self, place_id: &str) -> TweetBuilder {
+        self.place_id = Some(String::from(place_id));
+        self
+    }
+
+    pub fn possibly_sensitive(mut self, value: bool) -> TweetBuilder {
+        self.possibly_sensitive = value;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), TweetError> {
+        if self.text.is_empty() && self.media.is_empty() && self.attachment_url.is_none() {
+            return Err(TweetError::Empty);
+        }
+        let weighted_length = text::weighted_length(&self.text);
+        if weighted_length > text::MAX_WEIGHTED_LENGTH {
+            return Err(TweetError::TooLong(weighted_length));
+        }
+        if self.media.len() > MAX_MEDIA_COUNT {
+            return Err(TweetError::TooManyMedia(self.media.len()));
+        }
+        let attachments = [
+            !self.media.is_empty(),
+            self.attachment_url.is_some(),
+            self.poll.is_some(),
+        ];
+        if attachments.iter().filter(|it| **it).count() > 1 {
+            return Err(TweetError::ConflictingAttachments);
+        }
+        if self.auto_populate_reply_metadata && self.in_reply_to_status_id.is_none() {
+            return Err(TweetError::ReplyMetadataWithoutReply);
+        }
+        if let Some((lat, long)) = self.coordinates {
+            if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&long) {
+                return Err(TweetError::InvalidCoordinates { lat, long });
+            }
+        }
+        if let Some(ref poll) = self.poll {
+            if !POLL_OPTIONS_RANGE.contains(&poll.options.len()) {
+                return Err(TweetError::InvalidPoll(String::from("options")));
+            }
+            if poll
+                .options
+                .iter()
+                .any(|it| it.is_empty() || it.chars().count() > MAX_POLL_OPTION_LENGTH)
+            {
+                return Err(TweetError::InvalidPoll(String::from("option length")));
+            }
+            if !POLL_DURATION_MINUTES_RANGE.contains(&poll.duration_minutes) {
+                return Err(TweetError::InvalidPoll(String::from("duration_minutes")));
+            }
+            if self.coordinates.is_some() {
+                return Err(TweetError::CoordinatesWithPoll);
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate, upload media and post.
+    /// Poll tweet is posted by /2/tweets and only id and text are filled.
+    pub async fn post<T: Twapi + Sync + ?Sized>(&self, twapi: &T) -> Result<Tweet, TwapiError> {
+        self.validate()?;
+        let media_ids = self.upload_media(twapi).await?;
+        if self.poll.is_some() {
+            return self.post_v2(twapi).await;
+        }
+        let params = self.to_params(&media_ids);
+        let params: Vec<(&str, &str)> = params.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        twapi.post_statuses_update(&params).await?.parse()
+    }
+
+    /// Parameters of statuses/update with uploaded media_ids
+    pub fn to_params(&self, media_ids: &[String]) -> Vec<(&'static str, String)> {
+        let mut params = vec![("status", self.text.clone())];
+        if !media_ids.is_empty() {
+            params.push(("media_ids", media_ids.join(",")));
+        }
+        if let Some(ref id) = self.in_reply_to_status_id {
+            params.push(("in_reply_to_status_id", id.clone()));
+        }
+        if self.auto_populate_reply_metadata {
+            params.push(("auto_populate_reply_metadata", String::from("true")));
+        }
+        if !self.exclude_reply_user_ids.is_empty() {
+            params.push((
+                "exclude_reply_user_ids",
+                self.exclude_reply_user_ids.join(","),
+            ));
+        }
+        if let Some(ref url) = self.attachment_url {
+            params.push(("attachment_url", url.clone()));
+        }
+        if let Some((lat, long)) = self.coordinates {
+            params.push(("lat", lat.to_string()));
+            params.push(("long", long.to_string()));
+        }
+        if self.display_coordinates {
+            params.push(("display_coordinates", String::from("true")));
+        }
+        if let Some(ref place_id) = self.place_id {
+            params.push(("place_id", place_id.clone()));
+        }
+        if self.possibly_sensitive {
+            params.push(("possibly_sensitive", String::from("true")));
+        }
+        params
+    }
+
+    async fn upload_media<T: Twapi + Sync + ?Sized>(
+        &self,
+        twapi: &T,
+    ) -> Result<Vec<String>, TwapiError> {
+        let mut media_ids = vec![];
+        for media in self.media.iter() {
+            let media_id = match media {
+                MediaSource::Id(media_id) => media_id.clone(),
+                MediaSource::Path { path, alt_text } => {
+                    let result = MediaUploader::auto(MediaUsage::Tweet)
+                        .upload(twapi, path)
+                        .await?;
+                    let media_id = media_id_of(&result)?;
+                    if let Some(alt_text) = alt_text {
+                        MediaMetadata::new(&media_id)
+                            .alt_text(alt_text)
+                            .create(twapi)
+                            .await?;
+                    }
+                    media_id
+                }
+            };
+            media_ids.push(media_id);
+        }
+        Ok(media_ids)
+    }
+
+    async fn post_v2<T: Twapi + Sync + ?Sized>(&self, twapi: &T) -> Result<Tweet, TwapiError> {
+        let mut value = json!({ "text": self.text });
+        if let Some(ref poll) = self.poll {
+            value["poll"] = json!({
+                "options": poll.options,
+                "duration_minutes": poll.duration_minutes,
+            });
+        }
+        if let Some(ref id) = self.in_reply_to_status_id {
+            value["reply"] = json!({
+                "in_reply_to_tweet_id": id,
+                "exclude_reply_user_ids": self.exclude_reply_user_ids,
+            });
+        }
+        if let Some(ref place_id) = self.place_id {
+            value["geo"] = json!({ "place_id": place_id });
+        }
+        let json = twapi
+            .post_v2_tweets(&value)
+            .await?
+            .into_result()?
+            .json
+            .ok_or(TwapiError::NotExists)?;
+        let data = json.get("data").ok_or(TwapiError::NotExists)?;
+        let id_str = data
+            .get("id")
+            .and_then(|it| it.as_str())
+            .ok_or(TwapiError::NotExists)?;
+        Ok(Tweet {
+            id: id_str.parse().unwrap_or_default(),
+            id_str: String::from(id_str),
+            text: data
+                .get("text")
+                .and_then(|it| it.as_str())
+                .map(String::from),
+            ..Default::default()
+        })
+    }
+}
+
+/// Tweet posted as a part of Thread
+#[derive(Debug, Clone, PartialEq)]
+pub struct PostedPart {
+    pub index: usize,
+    pub tweet: Tweet,
+}
+
+/// Thread stopped in the middle
+#[derive(Debug)]
+pub struct ThreadError {
+    /// Parts posted before the failure, pass to Thread::resume or Thread::rollback
+    pub posted: Vec<PostedPart>,
+    pub failed_index: usize,
+    pub error: TwapiError,
+}
+
+/// Parts posted as a chain of replies
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Thread {
+    parts: Vec<TweetBuilder>,
+    in_reply_to_status_id: Option<String>,
+}
+
+impl Thread {
+    /// in_reply_to_status_id of each part is overwritten
+    pub fn new(parts: Vec<TweetBuilder>) -> Thread {
+        Thread {
+            parts,
+            in_reply_to_status_id: None,
+        }
+    }
+
+    pub fn from_texts(texts: &[&str]) -> Thread {
+        Thread::new(texts.iter().map(|it| TweetBuilder::new(it)).collect())
+    }
+
+    /// Reply the first part to an existing tweet
+    pub fn in_reply_to_status_id(mut self, id: &str) -> Thread {
+        self.in_reply_to_status_id = Some(String::from(id));
+        self
+    }
+
+    /// Index and error of the first invalid part
+    pub fn validate(&self) -> Result<(), (usize, TweetError)> {
+        for (index, part) in self.parts.iter().enumerate() {
+            part.validate().map_err(|err| (index, err))?;
+        }
+        Ok(())
+    }
+
+    pub async fn post<T: Twapi + Sync + ?Sized>(
+        &self,
+        twapi: &T,
+    ) -> Result<Vec<PostedPart>, ThreadError> {
+        self.resume(twapi, vec![]).await
+    }
+
+    /// Continue after posted parts of ThreadError
+    pub async fn resume<T: Twapi + Sync + ?Sized>(
+        &self,
+        twapi: &T,
+        mut posted: Vec<PostedPart>,
+    ) -> Result<Vec<PostedPart>, ThreadError> {
+        if let Err((failed_index, err)) = self.validate() {
+            return Err(ThreadError {
+                posted,
+                failed_index,
+                error: err.into(),
+            });
+        }
+        let start = posted.len();
+        for (index, part) in self.parts.iter().enumerate().skip(start) {
+            let reply_to = match posted.last() {
+                Some(last) => Some(last.tweet.id_str.clone()),
+                None => self.in_reply_to_status_id.clone(),
+            };
+            let part = match reply_to {
+                Some(ref id) => part.clone().in_reply_to_status_id(id),
+                None => part.clone(),
+            };
+            match part.post(twapi).await {
+                Ok(tweet) => posted.push(PostedPart { index, tweet }),
+                Err(error) => {
+                    return Err(ThreadError {
+                        posted,
+                        failed_index: index,
+                        error,
+                    })
+                }
+            }
+        }
+        Ok(posted)
+    }
+
+    /// Delete posted parts from the last one, ids of deleted tweets in that order
+    pub async fn rollback<T: Twapi + Sync + ?Sized>(
+        twapi: &T,
+        posted: &[PostedPart],
+    ) -> Result<Vec<String>, RollbackError> {
+        let mut deleted = vec![];
+        for (position, part) in posted.iter().enumerate().rev() {
+            let result = twapi
+                .post_statuses_destroy(&part.tweet.id_str)
+                .await
+                .and_then(|it| it.into_result());
+            if let Err(error) = result {
+                return Err(RollbackError {
+                    deleted,
+                    remaining: posted[..=position].to_vec(),
+                    error,
+                });
+            }
+            deleted.push(part.tweet.id_str.clone());
+        }
+        Ok(deleted)
+    }
+}
+
+/// Rollback stopped in the middle
+#[derive(Debug)]
+pub struct RollbackError {
+    /// Ids of deleted tweets, last part first
+    pub deleted: Vec<String>,
+    /// Parts not deleted including the failed one, pass to Thread::rollback again
+    pub remaining: Vec<PostedPart>,
+    pub error: TwapiError,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockResponse, MockTwapi};
+
+    fn params(builder: &TweetBuilder, media_ids: &[&str]) -> Vec<(&'static str, String)> {
+        let media_ids: Vec<String> = media_ids.iter().map(|it| String::from(*it)).collect();
+        builder.to_params(&media_ids)
+    }
+
+    fn pairs(pairs: &[(&'static str, &str)]) -> Vec<(&'static str, String)> {
+        pairs.iter().map(|(k, v)| (*k, String::from(*v))).collect()
+    }
+
+    #[test]
+    fn rejects_empty_tweet() {
+        assert_eq!(TweetBuilder::new("").validate(), Err(TweetError::Empty));
+        assert_eq!(TweetBuilder::new("").media_id("1").validate(), Ok(()));
+        assert_eq!(TweetBuilder::new("").quote("1").validate(), Ok(()));
+    }
+
+    #[test]
+    fn checks_weighted_length() {
+        assert_eq!(TweetBuilder::new(&"a".repeat(280)).validate(), Ok(()));
+        assert_eq!(
+            TweetBuilder::new(&"a".repeat(281)).validate(),
+            Err(TweetError::TooLong(281))
+        );
+        assert_eq!(TweetBuilder::new(&"あ".repeat(140)).validate(), Ok(()));
+        assert_eq!(
+            TweetBuilder::new(&"あ".repeat(141)).validate(),
+            Err(TweetError::TooLong(282))
+        );
+        // a url counts as 23 whatever its length
+        let text = format!(
+            "{} https://example.com/{}",
+            "a".repeat(256),
+            "b".repeat(100)
+        );
+        assert_eq!(TweetBuilder::new(&text).validate(), Ok(()));
+    }
+
+    #[test]
+    fn limits_media_count() {
+        let builder = (0..MAX_MEDIA_COUNT).fold(TweetBuilder::new("media"), |builder, i| {
+            builder.media_id(&i.to_string())
+        });
+        assert_eq!(builder.validate(), Ok(()));
+        assert_eq!(
+            builder.media_path("photo.png").validate(),
+            Err(TweetError::TooManyMedia(5))
+        );
+    }
+
+    #[test]
+    fn rejects_conflicting_attachments() {
+        let poll = &["yes", "no"];
+        assert_eq!(
+            TweetBuilder::new("q")
+                .media_id("1")
+                .poll(poll, 60)
+                .validate(),
+            Err(TweetError::ConflictingAttachments)
+        );
+        assert_eq!(
+            TweetBuilder::new("q").media_id("1").quote("2").validate(),
+            Err(TweetError::ConflictingAttachments)
+        );
+        assert_eq!(
+            TweetBuilder::new("q").quote("2").poll(poll, 60).validate(),
+            Err(TweetError::ConflictingAttachments)
+        );
+        assert_eq!(TweetBuilder::new("q").poll(poll, 60).validate(), Ok(()));
+    }
+
+    #[test]
+    fn checks_reply_metadata_coordinates_and_poll() {
+        assert_eq!(
+            TweetBuilder::new("hi")
+                .auto_populate_reply_metadata(true)
+                .validate(),
+            Err(TweetError::ReplyMetadataWithoutReply)
+        );
+        assert_eq!(
+            TweetBuilder::new("hi").coordinates(91.0, 0.0).validate(),
+            Err(TweetError::InvalidCoordinates {
+                lat: 91.0,
+                long: 0.0
+            })
+        );
+        assert_eq!(
+            TweetBuilder::new("q").poll(&["yes"], 60).validate(),
+            Err(TweetError::InvalidPoll(String::from("options")))
+        );
+        assert_eq!(
+            TweetBuilder::new("q").poll(&["yes", ""], 60).validate(),
+            Err(TweetError::InvalidPoll(String::from("option length")))
+        );
+        assert_eq!(
+            TweetBuilder::new("q").poll(&["yes", "no"], 4).validate(),
+            Err(TweetError::InvalidPoll(String::from("duration_minutes")))
+        );
+        assert_eq!(
+            TweetBuilder::new("q")
+                .poll(&["yes", "no"], 60)
+                .coordinates(35.0, 139.0)
+                .validate(),
+            Err(TweetError::CoordinatesWithPoll)
+        );
+    }
+
+    #[test]
+    fn reply_params() {
+        let builder = TweetBuilder::new("hi")
+            .in_reply_to_status_id("10")
+            .auto_populate_reply_metadata(true)
+            .exclude_reply_user_id("1")
+            .exclude_reply_user_id("2");
+        assert_eq!(
+            params(&builder, &["m1", "m2"]),
+            pairs(&[
+                ("status", "hi"),
+                ("media_ids", "m1,m2"),
+                ("in_reply_to_status_id", "10"),
+                ("auto_populate_reply_metadata", "true"),
+                ("exclude_reply_user_ids", "1,2"),
+            ])
+        );
+    }
+
+    #[test]
+    fn quote_and_location_params() {
+        let builder = TweetBuilder::new("look")
+            .quote("20")
+            .coordinates(35.5, 139.25)
+            .display_coordinates(true)
+            .place_id("p1")
+            .possibly_sensitive(true);
+        assert_eq!(
+            params(&builder, &[]),
+            pairs(&[
+                ("status", "look"),
+                ("attachment_url", "https://twitter.com/i/web/status/20"),
+                ("lat", "35.5"),
+                ("long", "139.25"),
+                ("display_coordinates", "true"),
+                ("place_id", "p1"),
+                ("possibly_sensitive", "true"),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn posts_statuses_update() {
+        let twapi = MockTwapi::new();
+        twapi.push(MockResponse::new(
+            200,
+            serde_json::json!({"id": 30, "id_str": "30", "full_text": "hi"}),
+        ));
+        let tweet = TweetBuilder::new("hi")
+            .media_id("m1")
+            .post(&twapi)
+            .await
+            .unwrap();
+        assert_eq!(tweet.id_str, "30");
+        let requests = twapi.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(
+            requests[0].uri,
+            "https://api.twitter.com/1.1/statuses/update.json"
+        );
+        assert_eq!(requests[0].param("media_ids"), Some("m1"));
+    }
+
+    #[tokio::test]
+    async fn posts_poll_by_v2() {
+        let twapi = MockTwapi::new();
+        twapi.push(MockResponse::new(
+            201,
+            serde_json::json!({"data": {"id": "40", "text": "q"}}),
+        ));
+        let tweet = TweetBuilder::new("q")
+            .poll(&["yes", "no"], 60)
+            .in_reply_to_status_id("10")
+            .exclude_reply_user_id("1")
+            .place_id("p1")
+            .post(&twapi)
+            .await
+            .unwrap();
+        assert_eq!(tweet.id, 40);
+        assert_eq!(tweet.id_str, "40");
+        assert_eq!(tweet.text.as_deref(), Some("q"));
+        let requests = twapi.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "JSON");
+        assert_eq!(requests[0].uri, "https://api.twitter.com/2/tweets");
+        assert_eq!(
+            requests[0].json,
+            Some(serde_json::json!({
+                "text": "q",
+                "poll": {"options": ["yes", "no"], "duration_minutes": 60},
+                "reply": {"in_reply_to_tweet_id": "10", "exclude_reply_user_ids": ["1"]},
+                "geo": {"place_id": "p1"}
+            }))
+        );
+    }
+
+    #[tokio::test]
+    async fn does_not_post_invalid_tweet() {
+        let twapi = MockTwapi::new();
+        let result = TweetBuilder::new("").post(&twapi).await;
+        match result {
+            Err(TwapiError::Tweet(TweetError::Empty)) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+        assert!(twapi.requests().is_empty());
+    }
+
+    fn tweet(id: &str) -> MockResponse {
+        MockResponse::new(
+            200,
+            serde_json::json!({"id": id.parse::<u64>().unwrap(), "id_str": id}),
+        )
+    }
+
+    fn forbidden() -> MockResponse {
+        MockResponse::new(
+            403,
+            serde_json::json!({"errors": [{"code": 187, "message": "Status is a duplicate."}]}),
+        )
+    }
+
+    fn posted(ids: &[&str]) -> Vec<PostedPart> {
+        ids.iter()
+            .enumerate()
+            .map(|(index, id)| PostedPart {
+                index,
+                tweet: Tweet {
+                    id: id.parse().unwrap(),
+                    id_str: String::from(*id),
+                    ..Default::default()
+                },
+            })
+            .collect()
+    }
+
+    fn updates(twapi: &MockTwapi) -> Vec<(String, Option<String>)> {
+        twapi
+            .requests()
+            .iter()
+            .map(|it| {
+                (
+                    String::from(it.param("status").unwrap()),
+                    it.param("in_reply_to_status_id").map(String::from),
+                )
+            })
+            .collect()
+    }
+
+    fn ids(parts: &[PostedPart]) -> Vec<(usize, &str)> {
+        parts
+            .iter()
+            .map(|it| (it.index, it.tweet.id_str.as_str()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn thread_replies_to_previous_part() {
+        let twapi = MockTwapi::new();
+        twapi.push(tweet("10")).push(tweet("11")).push(tweet("12"));
+        let posted = Thread::from_texts(&["a", "b", "c"])
+            .in_reply_to_status_id("1")
+            .post(&twapi)
+            .await
+            .unwrap();
+        assert_eq!(ids(&posted), vec![(0, "10"), (1, "11"), (2, "12")]);
+        assert_eq!(
+            updates(&twapi),
+            vec![
+                (String::from("a"), Some(String::from("1"))),
+                (String::from("b"), Some(String::from("10"))),
+                (String::from("c"), Some(String::from("11"))),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn thread_returns_posted_parts_on_failure() {
+        let twapi = MockTwapi::new();
+        twapi.push(tweet("10")).push(forbidden());
+        let err = Thread::from_texts(&["a", "b", "c"])
+            .post(&twapi)
+            .await
+            .unwrap_err();
+        assert_eq!(err.failed_index, 1);
+        assert_eq!(ids(&err.posted), vec![(0, "10")]);
+        match err.error {
+            TwapiError::Api(err) => assert_eq!(err.status_code, 403),
+            err => panic!("unexpected {:?}", err),
+        }
+        assert_eq!(
+            updates(&twapi),
+            vec![
+                (String::from("a"), None),
+                (String::from("b"), Some(String::from("10"))),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn thread_resumes_after_posted_parts() {
+        let twapi = MockTwapi::new();
+        twapi.push(tweet("12"));
+        let posted = Thread::from_texts(&["a", "b", "c"])
+            .in_reply_to_status_id("1")
+            .resume(&twapi, posted(&["10", "11"]))
+            .await
+            .unwrap();
+        assert_eq!(ids(&posted), vec![(0, "10"), (1, "11"), (2, "12")]);
+        assert_eq!(
+            updates(&twapi),
+            vec![(String::from("c"), Some(String::from("11")))]
+        );
+    }
+
+    #[tokio::test]
+    async fn thread_validates_every_part_before_posting() {
+        let twapi = MockTwapi::new();
+        let long = "a".repeat(281);
+        let err = Thread::from_texts(&["a", &long])
+            .post(&twapi)
+            .await
+            .unwrap_err();
+        assert_eq!(err.failed_index, 1);
+        assert!(err.posted.is_empty());
+        assert!(twapi.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rollback_deletes_from_last_part() {
+        let twapi = MockTwapi::new();
+        let deleted = Thread::rollback(&twapi, &posted(&["10", "11", "12"]))
+            .await
+            .unwrap();
+        assert_eq!(deleted, vec!["12", "11", "10"]);
+        let uris: Vec<_> = twapi.requests().into_iter().map(|it| it.uri).collect();
+        assert_eq!(
+            uris,
+            vec![
+                "https://api.twitter.com/1.1/statuses/destroy/12.json",
+                "https://api.twitter.com/1.1/statuses/destroy/11.json",
+                "https://api.twitter.com/1.1/statuses/destroy/10.json",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn rollback_reports_deleted_and_remaining_parts() {
+        let twapi = MockTwapi::new();
+        twapi.push(tweet("12")).push(forbidden());
+        let err = Thread::rollback(&twapi, &posted(&["10", "11", "12"]))
+            .await
+            .unwrap_err();
+        assert_eq!(err.deleted, vec!["12"]);
+        assert_eq!(ids(&err.remaining), vec![(0, "10"), (1, "11")]);
+        assert_eq!(twapi.requests().len(), 2);
+
+        let deleted = Thread::rollback(&twapi, &err.remaining).await.unwrap();
+        assert_eq!(deleted, vec!["11", "10"]);
+    }
+}
@@ -0,0 +1,241 @@
+//! Twitter API v2
+use super::{
+    pagination::{page_items, to_query, token_pages},
+    Twapi, TwapiError, TwapiResponse,
+};
+use async_trait::async_trait;
+use futures_util::stream::{self, Stream, StreamExt};
+
+/// Builder of expansions and *.fields parameters
+#[derive(Debug, Clone, Default)]
+pub struct V2Params {
+    params: Vec<(String, String)>,
+}
+
+impl V2Params {
+    pub fn new() -> V2Params {
+        V2Params::default()
+    }
+
+    pub fn expansions(self, values: &[&str]) -> V2Params {
+        self.param("expansions", &values.join(","))
+    }
+
+    pub fn tweet_fields(self, values: &[&str]) -> V2Params {
+        self.param("tweet.fields", &values.join(","))
+    }
+
+    pub fn user_fields(self, values: &[&str]) -> V2Params {
+        self.param("user.fields", &values.join(","))
+    }
+
+    pub fn media_fields(self, values: &[&str]) -> V2Params {
+        self.param("media.fields", &values.join(","))
+    }
+
+    pub fn place_fields(self, values: &[&str]) -> V2Params {
+        self.param("place.fields", &values.join(","))
+    }
+
+    pub fn poll_fields(self, values: &[&str]) -> V2Params {
+        self.param("poll.fields", &values.join(","))
+    }
+
+    /// Any other parameter such as query, ids or max_results
+    pub fn param(mut self, key: &str, value: &str) -> V2Params {
+        self.params.retain(|(it, _)| it != key);
+        self.params.push((String::from(key), String::from(value)));
+        self
+    }
+
+    pub fn to_vec(&self) -> Vec<(&str, &str)> {
+        to_query(&self.params)
+    }
+}
+
+/// Twitter API v2 endpoints, available on every Twapi
+#[allow(clippy::ptr_arg)]
+#[async_trait]
+pub trait TwapiV2: Twapi {
+    async fn get_v2_tweets(&self, params: &Vec<(&str, &str)>) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .get(&format!("{}/2/tweets", self.api_base_url()), params)
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
+
+    async fn get_v2_tweets_id(
+        &self,
+        id: &str,
+        params: &Vec<(&str, &str)>,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .get(&format!("{}/2/tweets/{}", self.api_base_url(), id), params)
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
+
+    async fn get_v2_tweets_search_recent(
+        &self,
+        params: &Vec<(&str, &str)>,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .get(
+                &format!("{}/2/tweets/search/recent", self.api_base_url()),
+                params,
+            )
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
+
+    async fn get_v2_users_by_username(
+        &self,
+        username: &str,
+        params: &Vec<(&str, &str)>,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .get(
+                &format!("{}/2/users/by/username/{}", self.api_base_url(), username),
+                params,
+            )
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
+
+    async fn get_v2_users_tweets(
+        &self,
+        id: &str,
+        params: &Vec<(&str, &str)>,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .get(
+                &format!("{}/2/users/{}/tweets", self.api_base_url(), id),
+                params,
+            )
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
+
+    async fn get_v2_users_followers(
+        &self,
+        id: &str,
+        params: &Vec<(&str, &str)>,
+    ) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .get(
+                &format!("{}/2/users/{}/followers", self.api_base_url(), id),
+                params,
+            )
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
+
+    async fn post_v2_tweets(&self, value: &serde_json::Value) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .json(&format!("{}/2/tweets", self.api_base_url()), &vec![], value)
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
+
+    async fn delete_v2_tweets(&self, id: &str) -> Result<TwapiResponse, TwapiError> {
+        let res = self
+            .delete(&format!("{}/2/tweets/{}", self.api_base_url(), id), &vec![])
+            .await?;
+        Ok(TwapiResponse::new(res).await)
+    }
+}
+
+impl<T: Twapi + Sync + ?Sized> TwapiV2 for T {}
+
+/// Stream each page of a v2 endpoint following meta.next_token.
+/// token_param is "next_token" for search, "pagination_token" for users/:id/*.
+pub fn pages<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    uri: &str,
+    params: &[(&str, &str)],
+    token_param: &str,
+    max_pages: Option<usize>,
+) -> impl Stream<Item = Result<serde_json::Value, TwapiError>> + Send + 'a {
+    token_pages(twapi, uri, params, token_param, next_token, max_pages)
+}
+
+fn next_token(json: &serde_json::Value) -> Option<String> {
+    json.get("meta")?
+        .get("next_token")?
+        .as_str()
+        .map(String::from)
+}
+
+/// Stream each item of data in the pages of a v2 endpoint
+pub fn items<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    uri: &str,
+    params: &[(&str, &str)],
+    token_param: &str,
+    max_pages: Option<usize>,
+) -> impl Stream<Item = Result<serde_json::Value, TwapiError>> + Send + 'a {
+    pages(twapi, uri, params, token_param, max_pages)
+        .flat_map(|page| stream::iter(page_items(page, "data")))
+}
+
+/// /2/tweets/search/recent
+pub fn search_recent_stream<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    params: &[(&str, &str)],
+    max_pages: Option<usize>,
+) -> impl Stream<Item = Result<serde_json::Value, TwapiError>> + Send + 'a {
+    let uri = format!("{}/2/tweets/search/recent", twapi.api_base_url());
+    items(twapi, &uri, params, "next_token", max_pages)
+}
+
+/// /2/users/:id/tweets
+pub fn users_tweets_stream<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    id: &str,
+    params: &[(&str, &str)],
+    max_pages: Option<usize>,
+) -> impl Stream<Item = Result<serde_json::Value, TwapiError>> + Send + 'a {
+    let uri = format!("{}/2/users/{}/tweets", twapi.api_base_url(), id);
+    items(twapi, &uri, params, "pagination_token", max_pages)
+}
+
+/// /2/users/:id/followers
+pub fn users_followers_stream<'a, T: Twapi + Sync + ?Sized>(
+    twapi: &'a T,
+    id: &str,
+    params: &[(&str, &str)],
+    max_pages: Option<usize>,
+) -> impl Stream<Item = Result<serde_json::Value, TwapiError>> + Send + 'a {
+    let uri = format!("{}/2/users/{}/followers", twapi.api_base_url(), id);
+    items(twapi, &uri, params, "pagination_token", max_pages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockResponse, MockTwapi};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn follows_next_token() {
+        let twapi = MockTwapi::new();
+        twapi
+            .push(MockResponse::new(
+                200,
+                json!({"data": [{"id": "1"}], "meta": {"next_token": "abc"}}),
+            ))
+            .push(MockResponse::new(
+                200,
+                json!({"data": [{"id": "2"}], "meta": {}}),
+            ));
+        let tweets: Vec<_> = users_tweets_stream(&twapi, "9", &[], None)
+            .map(|it| it.unwrap())
+            .collect()
+            .await;
+        assert_eq!(tweets, vec![json!({"id": "1"}), json!({"id": "2"})]);
+        let requests = twapi.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].uri, "https://api.twitter.com/2/users/9/tweets");
+        assert_eq!(requests[1].param("pagination_token"), Some("abc"));
+    }
+}
@@ -0,0 +1,626 @@
+//! Idempotent registration of Account Activity webhooks and subscriptions,
+//! including subscriptions of many users
+use crate::{Twapi, TwapiError, TwapiResponse, UserAuth};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fmt};
+
+/// Webhook config of an environment
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Webhook {
+    pub id: String,
+    pub url: String,
+    pub valid: bool,
+    pub created_timestamp: String,
+}
+
+/// What ensure_webhook changed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WebhookReport {
+    pub webhook: Webhook,
+    pub created: bool,
+    /// CRC was triggered because the webhook was invalid
+    pub revalidated: bool,
+    /// Webhooks of other urls
+    pub deleted: Vec<Webhook>,
+}
+
+impl WebhookReport {
+    pub fn is_changed(&self) -> bool {
+        self.created || self.revalidated || !self.deleted.is_empty()
+    }
+}
+
+/// What ensure_subscription changed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SubscriptionReport {
+    pub created: bool,
+}
+
+/// Reconciles webhook and subscription of an environment.
+/// app lists webhooks, user registers them and subscribes itself.
+pub struct WebhookManager<'a, A: ?Sized, U: ?Sized> {
+    app: &'a A,
+    user: &'a U,
+    env_name: String,
+}
+
+impl<'a, A, U> WebhookManager<'a, A, U>
+where
+    A: Twapi + Sync + ?Sized,
+    U: Twapi + Sync + ?Sized,
+{
+    pub fn new(app: &'a A, user: &'a U, env_name: &str) -> WebhookManager<'a, A, U> {
+        WebhookManager {
+            app,
+            user,
+            env_name: String::from(env_name),
+        }
+    }
+
+    pub fn env_name(&self) -> &str {
+        &self.env_name
+    }
+
+    pub async fn list_webhooks(&self) -> Result<Vec<Webhook>, TwapiError> {
+        let json = self
+            .app
+            .get_account_activity_webhooks(Some(&self.env_name))
+            .await?
+            .into_result()?
+            .json
+            .unwrap_or_default();
+        parse_webhooks(json)
+    }
+
+    /// Make url the only webhook of the environment and valid
+    pub async fn ensure_webhook(&self, url: &str) -> Result<WebhookReport, TwapiError> {
+        let mut report = WebhookReport::default();
+        let mut current = None;
+        for webhook in self.list_webhooks().await? {
+            if webhook.url == url && current.is_none() {
+                current = Some(webhook);
+                continue;
+            }
+            self.user
+                .delete_account_activity_webhooks(&webhook.id, Some(&self.env_name))
+                .await?
+                .into_result()?;
+            report.deleted.push(webhook);
+        }
+        report.webhook = match current {
+            Some(mut webhook) => {
+                if !webhook.valid {
+                    self.user
+                        .put_account_activity_webhooks_id(&webhook.id, Some(&self.env_name))
+                        .await?
+                        .into_result()?;
+                    webhook.valid = true;
+                    report.revalidated = true;
+                }
+                webhook
+            }
+            None => {
+                report.created = true;
+                self.user
+                    .post_account_activity_webhooks(url, Some(&self.env_name))
+                    .await?
+                    .parse()?
+            }
+        };
+        Ok(report)
+    }
+
+    /// Subscribe the user unless already subscribed
+    pub async fn ensure_subscription(&self) -> Result<SubscriptionReport, TwapiError> {
+        let result = self
+            .user
+            .get_account_activity_subscriptions(Some(&self.env_name))
+            .await?;
+        if is_subscribed(&result)? {
+            return Ok(SubscriptionReport { created: false });
+        }
+        self.user
+            .post_account_activity_subscriptions(Some(&self.env_name))
+            .await?
+            .into_result()?;
+        Ok(SubscriptionReport { created: true })
+    }
+
+    /// Webhook and subscription
+    pub async fn ensure(
+        &self,
+        url: &str,
+    ) -> Result<(WebhookReport, SubscriptionReport), TwapiError> {
+        let webhook = self.ensure_webhook(url).await?;
+        let subscription = self.ensure_subscription().await?;
+        Ok((webhook, subscription))
+    }
+}
+
+/// Access token of a user to subscribe
+#[derive(Clone, PartialEq, Eq)]
+pub struct UserToken {
+    pub user_id: String,
+    pub access_token: String,
+    pub access_token_secret: String,
+}
+
+// access_token_secret is not printed
+impl fmt::Debug for UserToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UserToken")
+            .field("user_id", &self.user_id)
+            .field("access_token", &self.access_token)
+            .field("access_token_secret", &"<redacted>")
+            .finish()
+    }
+}
+
+/// What SubscriptionManager::sync changed
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub subscribed: Vec<String>,
+    pub unsubscribed: Vec<String>,
+    pub unchanged: Vec<String>,
+    /// User id and error, other users are still synced
+    pub failed: Vec<(String, TwapiError)>,
+}
+
+/// Factory of UserAuth on the same api and upload base urls as app
+pub fn user_auth_factory<A: Twapi + ?Sized>(
+    app: &A,
+    consumer_key: &str,
+    consumer_secret: &str,
+) -> impl Fn(&UserToken) -> UserAuth + Send + Sync {
+    let consumer_key = String::from(consumer_key);
+    let consumer_secret = String::from(consumer_secret);
+    let api_base_url = String::from(app.api_base_url());
+    let upload_base_url = String::from(app.upload_base_url());
+    move |token| {
+        UserAuth::new(
+            &consumer_key,
+            &consumer_secret,
+            &token.access_token,
+            &token.access_token_secret,
+        )
+        .with_api_base_url(&api_base_url)
+        .with_upload_base_url(&upload_base_url)
+    }
+}
+
+/// Subscriptions of many users to an environment.
+/// Each user subscribes with the Twapi built by user_auth from its token,
+/// app lists and deletes subscriptions.
+pub struct SubscriptionManager<'a, A: ?Sized, F> {
+    app: &'a A,
+    env_name: String,
+    user_auth: F,
+}
+
+impl<'a, A, F, U> SubscriptionManager<'a, A, F>
+where
+    A: Twapi + Sync + ?Sized,
+    F: Fn(&UserToken) -> U + Sync,
+    U: Twapi + Sync,
+{
+    /// user_auth is e.g. user_auth_factory or a closure wrapping its UserAuth in RetryTwapi
+    pub fn new(app: &'a A, env_name: &str, user_auth: F) -> SubscriptionManager<'a, A, F> {
+        SubscriptionManager {
+            app,
+            env_name: String::from(env_name),
+            user_auth,
+        }
+    }
+
+    pub fn user_auth(&self, token: &UserToken) -> U {
+        (self.user_auth)(token)
+    }
+
+    /// User ids of active subscriptions
+    pub async fn list(&self) -> Result<Vec<String>, TwapiError> {
+        let json = self
+            .app
+            .get_account_activity_subscriptions_list(Some(&self.env_name))
+            .await?
+            .into_result()?
+            .json
+            .unwrap_or_default();
+        Ok(json
+            .get("subscriptions")
+            .and_then(|it| it.as_array())
+            .map(|subscriptions| {
+                subscriptions
+                    .iter()
+                    .filter_map(|it| it.get("user_id").and_then(|it| it.as_str()))
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Subscribe the user unless already subscribed
+    pub async fn subscribe(&self, token: &UserToken) -> Result<SubscriptionReport, TwapiError> {
+        let user = self.user_auth(token);
+        let result = user
+            .get_account_activity_subscriptions(Some(&self.env_name))
+            .await?;
+        if is_subscribed(&result)? {
+            return Ok(SubscriptionReport { created: false });
+        }
+        user.post_account_activity_subscriptions(Some(&self.env_name))
+            .await?
+            .into_result()?;
+        Ok(SubscriptionReport { created: true })
+    }
+
+    pub async fn unsubscribe(&self, user_id: &str) -> Result<(), TwapiError> {
+        self.app
+            .delete_account_activity_subscriptions_user_id(user_id, &self.env_name)
+            .await?
+            .into_result()?;
+        Ok(())
+    }
+
+    /// Subscribe missing users of desired and unsubscribe users not in desired
+    pub async fn sync(&self, desired: &[UserToken]) -> Result<SyncReport, TwapiError> {
+        let current: HashSet<String> = self.list().await?.into_iter().collect();
+        let desired_ids: HashSet<&str> = desired.iter().map(|it| it.user_id.as_str()).collect();
+        let mut report = SyncReport::default();
+        for token in desired.iter() {
+            if current.contains(&token.user_id) {
+                report.unchanged.push(token.user_id.clone());
+                continue;
+            }
+            let result = self
+                .user_auth(token)
+                .post_account_activity_subscriptions(Some(&self.env_name))
+                .await
+                .and_then(|it| it.into_result());
+            match result {
+                Ok(_) => report.subscribed.push(token.user_id.clone()),
+                Err(err) => report.failed.push((token.user_id.clone(), err)),
+            }
+        }
+        for user_id in current.iter() {
+            if desired_ids.contains(user_id.as_str()) {
+                continue;
+            }
+            match self.unsubscribe(user_id).await {
+                Ok(_) => report.unsubscribed.push(user_id.clone()),
+                Err(err) => report.failed.push((user_id.clone(), err)),
+            }
+        }
+        Ok(report)
+    }
+}
+
+// Array for all/:env_name/webhooks, environments for all/webhooks
+fn parse_webhooks(json: serde_json::Value) -> Result<Vec<Webhook>, TwapiError> {
+    match json {
+        serde_json::Value::Array(_) => Ok(serde_json::from_value(json)?),
+        json => {
+            let mut webhooks = vec![];
+            if let Some(environments) = json.get("environments").and_then(|it| it.as_array()) {
+                for environment in environments {
+                    if let Some(items) = environment.get("webhooks") {
+                        let items: Vec<Webhook> = serde_json::from_value(items.clone())?;
+                        webhooks.extend(items);
+                    }
+                }
+            }
+            Ok(webhooks)
+        }
+    }
+}
+
+// 204 if subscribed, 404 if not
+fn is_subscribed(result: &TwapiResponse) -> Result<bool, TwapiError> {
+    match result.status_code {
+        204 => Ok(true),
+        404 => Ok(false),
+        _ => match result.error() {
+            Some(err) => Err(TwapiError::Api(err)),
+            None => Ok(true),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockResponse, MockTwapi};
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    const SUBSCRIPTIONS: &str =
+        "https://api.twitter.com/1.1/account_activity/all/env/subscriptions";
+
+    fn token(user_id: &str) -> UserToken {
+        UserToken {
+            user_id: String::from(user_id),
+            access_token: format!("token{}", user_id),
+            access_token_secret: format!("secret{}", user_id),
+        }
+    }
+
+    fn subscriptions(user_ids: &[&str]) -> MockResponse {
+        let subscriptions: Vec<_> = user_ids.iter().map(|it| json!({ "user_id": it })).collect();
+        MockResponse::new(200, json!({ "subscriptions": subscriptions }))
+    }
+
+    fn forbidden() -> MockResponse {
+        MockResponse::new(
+            403,
+            json!({"errors": [{"code": 89, "message": "Invalid or expired token."}]}),
+        )
+    }
+
+    fn sorted(mut user_ids: Vec<String>) -> Vec<String> {
+        user_ids.sort();
+        user_ids
+    }
+
+    const WEBHOOKS: &str = "https://api.twitter.com/1.1/account_activity/all/env/webhooks";
+    const URL: &str = "https://example.com/webhook";
+
+    fn webhook(id: &str, url: &str, valid: bool) -> serde_json::Value {
+        json!({"id": id, "url": url, "valid": valid, "created_timestamp": "2021-03-26 00:00:00 +0000"})
+    }
+
+    fn methods(twapi: &MockTwapi) -> Vec<(&'static str, String)> {
+        twapi
+            .requests()
+            .into_iter()
+            .map(|it| (it.method, it.uri))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn ensure_webhook_keeps_valid_webhook() {
+        let app = MockTwapi::new();
+        app.push(MockResponse::new(200, json!([webhook("1", URL, true)])));
+        let user = MockTwapi::new();
+        let report = WebhookManager::new(&app, &user, "env")
+            .ensure_webhook(URL)
+            .await
+            .unwrap();
+        assert!(!report.is_changed());
+        assert_eq!(report.webhook.id, "1");
+        assert_eq!(methods(&app), vec![("GET", format!("{}.json", WEBHOOKS))]);
+        assert!(user.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn ensure_webhook_deletes_other_urls() {
+        let app = MockTwapi::new();
+        app.push(MockResponse::new(
+            200,
+            json!({"environments": [{
+                "environment_name": "env",
+                "webhooks": [
+                    webhook("1", "https://example.com/old", true),
+                    webhook("2", URL, true),
+                    webhook("3", URL, true),
+                ]
+            }]}),
+        ));
+        let user = MockTwapi::new();
+        user.push(MockResponse::new(204, json!({})))
+            .push(MockResponse::new(204, json!({})));
+        let report = WebhookManager::new(&app, &user, "env")
+            .ensure_webhook(URL)
+            .await
+            .unwrap();
+        assert!(report.is_changed());
+        assert!(!report.created && !report.revalidated);
+        assert_eq!(report.webhook.id, "2");
+        let deleted: Vec<_> = report.deleted.iter().map(|it| it.id.as_str()).collect();
+        assert_eq!(deleted, vec!["1", "3"]);
+        assert_eq!(
+            methods(&user),
+            vec![
+                ("DELETE", format!("{}/1.json", WEBHOOKS)),
+                ("DELETE", format!("{}/3.json", WEBHOOKS))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn ensure_webhook_revalidates_invalid_webhook() {
+        let app = MockTwapi::new();
+        app.push(MockResponse::new(200, json!([webhook("1", URL, false)])));
+        let user = MockTwapi::new();
+        user.push(MockResponse::new(204, json!({})));
+        let report = WebhookManager::new(&app, &user, "env")
+            .ensure_webhook(URL)
+            .await
+            .unwrap();
+        assert!(report.revalidated && !report.created);
+        assert!(report.webhook.valid);
+        assert_eq!(
+            methods(&user),
+            vec![("PUT", format!("{}/1.json", WEBHOOKS))]
+        );
+    }
+
+    #[tokio::test]
+    async fn ensure_webhook_creates_missing_webhook() {
+        let app = MockTwapi::new();
+        app.push(MockResponse::new(200, json!([])));
+        let user = MockTwapi::new();
+        user.push(MockResponse::new(200, webhook("9", URL, true)));
+        let report = WebhookManager::new(&app, &user, "env")
+            .ensure_webhook(URL)
+            .await
+            .unwrap();
+        assert!(report.created && !report.revalidated);
+        assert_eq!(report.webhook.id, "9");
+        let requests = user.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].uri, format!("{}.json", WEBHOOKS));
+        assert_eq!(requests[0].param("url"), Some(URL));
+    }
+
+    #[tokio::test]
+    async fn ensure_webhook_stops_on_failed_delete() {
+        let app = MockTwapi::new();
+        app.push(MockResponse::new(
+            200,
+            json!([webhook("1", "https://example.com/old", true)]),
+        ));
+        let user = MockTwapi::new();
+        user.push(forbidden());
+        let result = WebhookManager::new(&app, &user, "env")
+            .ensure_webhook(URL)
+            .await;
+        assert!(result.is_err());
+        assert_eq!(user.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn ensure_subscription_keeps_existing_subscription() {
+        let app = MockTwapi::new();
+        let user = MockTwapi::new();
+        user.push(MockResponse::new(204, json!({})));
+        let report = WebhookManager::new(&app, &user, "env")
+            .ensure_subscription()
+            .await
+            .unwrap();
+        assert!(!report.created);
+        assert_eq!(
+            methods(&user),
+            vec![("GET", format!("{}.json", SUBSCRIPTIONS))]
+        );
+    }
+
+    #[tokio::test]
+    async fn ensure_subscription_subscribes_on_not_found() {
+        let app = MockTwapi::new();
+        let user = MockTwapi::new();
+        user.push(MockResponse::new(
+            404,
+            json!({"errors": [{"code": 34, "message": "Sorry, that page does not exist."}]}),
+        ))
+        .push(MockResponse::new(204, json!({})));
+        let report = WebhookManager::new(&app, &user, "env")
+            .ensure_subscription()
+            .await
+            .unwrap();
+        assert!(report.created);
+        assert_eq!(
+            methods(&user),
+            vec![
+                ("GET", format!("{}.json", SUBSCRIPTIONS)),
+                ("POST", format!("{}.json", SUBSCRIPTIONS))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn ensure_subscription_fails_on_other_errors() {
+        let app = MockTwapi::new();
+        let user = MockTwapi::new();
+        user.push(forbidden());
+        let result = WebhookManager::new(&app, &user, "env")
+            .ensure_subscription()
+            .await;
+        match result {
+            Err(TwapiError::Api(err)) => assert_eq!(err.status_code, 403),
+            result => panic!("unexpected {:?}", result),
+        }
+        assert_eq!(user.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn sync_subscribes_and_unsubscribes_the_difference() {
+        let app = MockTwapi::new();
+        app.push(subscriptions(&["1", "2", "5"]));
+        app.push(MockResponse::new(204, json!({})));
+        app.push(MockResponse::new(204, json!({})));
+        let built = Mutex::new(vec![]);
+        let manager = SubscriptionManager::new(&app, "env", |token: &UserToken| {
+            built.lock().unwrap().push(token.user_id.clone());
+            let user = MockTwapi::new();
+            if token.user_id == "4" {
+                user.push(forbidden());
+            } else {
+                user.push(MockResponse::new(204, json!({})));
+            }
+            user
+        });
+        let report = manager
+            .sync(&[token("1"), token("3"), token("4")])
+            .await
+            .unwrap();
+        assert_eq!(report.unchanged, vec!["1"]);
+        assert_eq!(report.subscribed, vec!["3"]);
+        assert_eq!(sorted(report.unsubscribed), vec!["2", "5"]);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, "4");
+        match &report.failed[0].1 {
+            TwapiError::Api(err) => assert_eq!(err.status_code, 403),
+            err => panic!("unexpected {:?}", err),
+        }
+        assert_eq!(*built.lock().unwrap(), vec!["3", "4"]);
+
+        let requests = app.requests();
+        assert_eq!(requests[0].uri, format!("{}/list.json", SUBSCRIPTIONS));
+        let mut deleted: Vec<_> = requests[1..]
+            .iter()
+            .map(|it| {
+                assert_eq!(it.method, "DELETE");
+                it.uri.clone()
+            })
+            .collect();
+        deleted.sort();
+        assert_eq!(
+            deleted,
+            vec![
+                format!("{}/2.json", SUBSCRIPTIONS),
+                format!("{}/5.json", SUBSCRIPTIONS)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn sync_reports_failed_unsubscribe() {
+        let app = MockTwapi::new();
+        app.push(subscriptions(&["1", "2"]));
+        app.push(forbidden());
+        let manager = SubscriptionManager::new(&app, "env", |_: &UserToken| MockTwapi::new());
+        let report = manager.sync(&[token("1")]).await.unwrap();
+        assert_eq!(report.unchanged, vec!["1"]);
+        assert!(report.subscribed.is_empty());
+        assert!(report.unsubscribed.is_empty());
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, "2");
+    }
+
+    #[tokio::test]
+    async fn sync_fails_when_list_fails() {
+        let app = MockTwapi::new();
+        app.push(forbidden());
+        let manager = SubscriptionManager::new(&app, "env", |_: &UserToken| MockTwapi::new());
+        assert!(manager.sync(&[token("1")]).await.is_err());
+        assert_eq!(app.requests().len(), 1);
+    }
+
+    #[test]
+    fn user_auth_factory_keeps_base_urls() {
+        let app = crate::ApplicationAuth::new("bearer")
+            .with_api_base_url("http://localhost:8080/")
+            .with_upload_base_url("http://localhost:8081");
+        let user = user_auth_factory(&app, "key", "secret")(&token("1"));
+        assert_eq!(user.api_base_url(), "http://localhost:8080");
+        assert_eq!(user.upload_base_url(), "http://localhost:8081");
+    }
+
+    #[test]
+    fn user_token_debug_redacts_secret() {
+        let debug = format!("{:?}", token("1"));
+        assert!(debug.contains("token1"), "{}", debug);
+        assert!(!debug.contains("secret1"), "{}", debug);
+    }
+}
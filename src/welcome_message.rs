@@ -0,0 +1,262 @@
+//! Default welcome message by welcome message rules
+use super::{
+    models::{TwapiTyped, WelcomeMessage, WelcomeMessageRule},
+    Twapi, TwapiError,
+};
+
+/// Result of set_default_welcome_message
+#[derive(Debug, Clone, PartialEq)]
+pub struct DefaultWelcomeMessage {
+    /// Rule pointing to the default welcome message
+    pub rule: WelcomeMessageRule,
+    /// Rules deleted to replace
+    pub removed: Vec<WelcomeMessageRule>,
+    /// False if the welcome message was already the default
+    pub changed: bool,
+}
+
+/// All rules, following next_cursor
+pub async fn list_rules<T: Twapi + Sync + ?Sized>(
+    twapi: &T,
+) -> Result<Vec<WelcomeMessageRule>, TwapiError> {
+    let mut rules = vec![];
+    let mut cursor: Option<String> = None;
+    loop {
+        let mut params = vec![("count", "50")];
+        if let Some(ref cursor) = cursor {
+            params.push(("cursor", cursor));
+        }
+        let list = twapi
+            .get_direct_messages_welcome_messages_rules_list_typed(&params)
+            .await?;
+        rules.extend(list.welcome_message_rules);
+        match list.next_cursor {
+            Some(next) if !next.is_empty() => cursor = Some(next),
+            _ => return Ok(rules),
+        }
+    }
+}
+
+/// Replace current rules with one for welcome_message_id.
+/// When creating the new rule fails, the removed rule is restored on a best-effort basis.
+pub async fn set_default_welcome_message<T: Twapi + Sync + ?Sized>(
+    twapi: &T,
+    welcome_message_id: &str,
+) -> Result<DefaultWelcomeMessage, TwapiError> {
+    let rules = list_rules(twapi).await?;
+    if let [rule] = rules.as_slice() {
+        if rule.welcome_message_id == welcome_message_id {
+            return Ok(DefaultWelcomeMessage {
+                rule: rule.clone(),
+                removed: vec![],
+                changed: false,
+            });
+        }
+    }
+    // only one rule can exist, so it must be deleted before creating
+    for rule in rules.iter() {
+        twapi
+            .delete_direct_messages_welcome_messages_rules_destroy(&rule.id)
+            .await?
+            .into_result()?;
+    }
+    match twapi
+        .post_direct_messages_welcome_messages_rules_new_typed(welcome_message_id)
+        .await
+    {
+        Ok(rule) => Ok(DefaultWelcomeMessage {
+            rule,
+            removed: rules,
+            changed: true,
+        }),
+        Err(err) => {
+            if let Some(previous) = rules.first() {
+                let _ = twapi
+                    .post_direct_messages_welcome_messages_rules_new(&previous.welcome_message_id)
+                    .await;
+            }
+            Err(err)
+        }
+    }
+}
+
+/// Create welcome message from body of welcome_messages/new and make it the default
+pub async fn create_default_welcome_message<T: Twapi + Sync + ?Sized>(
+    twapi: &T,
+    value: &serde_json::Value,
+) -> Result<(WelcomeMessage, DefaultWelcomeMessage), TwapiError> {
+    let welcome_message = twapi
+        .post_direct_messages_welcome_messages_new_typed(value)
+        .await?;
+    let default = set_default_welcome_message(twapi, &welcome_message.id).await?;
+    Ok((welcome_message, default))
+}
+
+/// Delete all rules, no welcome message is shown
+pub async fn clear_default_welcome_message<T: Twapi + Sync + ?Sized>(
+    twapi: &T,
+) -> Result<Vec<WelcomeMessageRule>, TwapiError> {
+    let rules = list_rules(twapi).await?;
+    for rule in rules.iter() {
+        twapi
+            .delete_direct_messages_welcome_messages_rules_destroy(&rule.id)
+            .await?
+            .into_result()?;
+    }
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockResponse, MockTwapi};
+    use serde_json::json;
+
+    const RULES: &str = "https://api.twitter.com/1.1/direct_messages/welcome_messages/rules";
+
+    fn rule(id: &str, welcome_message_id: &str) -> serde_json::Value {
+        json!({"id": id, "created_timestamp": "1", "welcome_message_id": welcome_message_id})
+    }
+
+    fn rules(rules: Vec<serde_json::Value>, next_cursor: Option<&str>) -> MockResponse {
+        let mut json = json!({ "welcome_message_rules": rules });
+        if let Some(next_cursor) = next_cursor {
+            json["next_cursor"] = json!(next_cursor);
+        }
+        MockResponse::new(200, json)
+    }
+
+    fn created(id: &str, welcome_message_id: &str) -> MockResponse {
+        MockResponse::new(
+            200,
+            json!({ "welcome_message_rule": rule(id, welcome_message_id) }),
+        )
+    }
+
+    fn forbidden() -> MockResponse {
+        MockResponse::new(
+            403,
+            json!({"errors": [{"code": 326, "message": "To protect our users from spam and other malicious activity, this account is temporarily locked."}]}),
+        )
+    }
+
+    fn summary(twapi: &MockTwapi) -> Vec<String> {
+        twapi
+            .requests()
+            .iter()
+            .map(|it| {
+                let uri = it.uri.trim_start_matches(RULES);
+                match it.method {
+                    "JSON" => format!(
+                        "JSON {} {}",
+                        uri,
+                        it.json.as_ref().unwrap()["welcome_message_rule"]["welcome_message_id"]
+                            .as_str()
+                            .unwrap()
+                    ),
+                    "DELETE" => format!("DELETE {} {}", uri, it.param("id").unwrap()),
+                    method => format!("{} {}", method, uri),
+                }
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn keeps_current_default() {
+        let twapi = MockTwapi::new();
+        twapi.push(rules(vec![rule("r1", "w1")], None));
+        let result = set_default_welcome_message(&twapi, "w1").await.unwrap();
+        assert!(!result.changed);
+        assert!(result.removed.is_empty());
+        assert_eq!(result.rule.id, "r1");
+        assert_eq!(summary(&twapi), vec!["GET /list.json"]);
+    }
+
+    #[tokio::test]
+    async fn replaces_rules_of_every_page() {
+        let twapi = MockTwapi::new();
+        twapi
+            .push(rules(vec![rule("r1", "w1")], Some("c2")))
+            .push(rules(vec![rule("r2", "w2")], None))
+            .push(MockResponse::new(204, json!({})))
+            .push(MockResponse::new(204, json!({})))
+            .push(created("r3", "w3"));
+        let result = set_default_welcome_message(&twapi, "w3").await.unwrap();
+        assert!(result.changed);
+        assert_eq!(result.rule.id, "r3");
+        let removed: Vec<_> = result.removed.iter().map(|it| it.id.as_str()).collect();
+        assert_eq!(removed, vec!["r1", "r2"]);
+        assert_eq!(
+            summary(&twapi),
+            vec![
+                "GET /list.json",
+                "GET /list.json",
+                "DELETE /destroy.json r1",
+                "DELETE /destroy.json r2",
+                "JSON /new.json w3",
+            ]
+        );
+        assert_eq!(twapi.requests()[1].param("cursor"), Some("c2"));
+    }
+
+    #[tokio::test]
+    async fn restores_previous_rule_when_create_fails() {
+        let twapi = MockTwapi::new();
+        twapi
+            .push(rules(vec![rule("r1", "w1")], None))
+            .push(MockResponse::new(204, json!({})))
+            .push(forbidden())
+            .push(created("r4", "w1"));
+        let result = set_default_welcome_message(&twapi, "w3").await;
+        match result {
+            Err(TwapiError::Api(err)) => assert_eq!(err.status_code, 403),
+            result => panic!("unexpected {:?}", result),
+        }
+        assert_eq!(
+            summary(&twapi),
+            vec![
+                "GET /list.json",
+                "DELETE /destroy.json r1",
+                "JSON /new.json w3",
+                "JSON /new.json w1",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn returns_create_error_when_restore_fails() {
+        let twapi = MockTwapi::new();
+        twapi
+            .push(rules(vec![rule("r1", "w1")], None))
+            .push(MockResponse::new(204, json!({})))
+            .push(forbidden())
+            .push(MockResponse::new(500, json!({})));
+        let result = set_default_welcome_message(&twapi, "w3").await;
+        match result {
+            Err(TwapiError::Api(err)) => assert_eq!(err.status_code, 403),
+            result => panic!("unexpected {:?}", result),
+        }
+        assert_eq!(twapi.requests().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn does_not_create_when_delete_fails() {
+        let twapi = MockTwapi::new();
+        twapi
+            .push(rules(vec![rule("r1", "w1")], None))
+            .push(forbidden());
+        assert!(set_default_welcome_message(&twapi, "w3").await.is_err());
+        assert_eq!(
+            summary(&twapi),
+            vec!["GET /list.json", "DELETE /destroy.json r1"]
+        );
+    }
+
+    #[tokio::test]
+    async fn creates_without_previous_rule() {
+        let twapi = MockTwapi::new();
+        twapi.push(rules(vec![], None)).push(forbidden());
+        assert!(set_default_welcome_message(&twapi, "w3").await.is_err());
+        assert_eq!(summary(&twapi), vec!["GET /list.json", "JSON /new.json w3"]);
+    }
+}